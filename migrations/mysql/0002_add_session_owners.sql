@@ -0,0 +1,7 @@
+ALTER TABLE sessions
+  ADD COLUMN `user_id` VARCHAR(255) NULL,
+  ADD COLUMN `created_at` DATETIME NULL,
+  ADD COLUMN `last_seen` DATETIME NULL,
+  ADD COLUMN `user_agent` TEXT NULL,
+  ADD COLUMN `ip` VARCHAR(64) NULL,
+  ADD KEY `sessions_user_id` (`user_id`);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS remember_tokens (
+  `selector` VARCHAR(64) NOT NULL,
+  `validator_hash` VARCHAR(128) NOT NULL,
+  `previous_hash` VARCHAR(128) NULL,
+  `rotated_at` DATETIME NULL,
+  `user_id` VARCHAR(255) NOT NULL,
+  `expires` DATETIME NOT NULL,
+  PRIMARY KEY (`selector`),
+  KEY `remember_tokens_user_id` (`user_id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
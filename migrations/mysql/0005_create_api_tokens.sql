@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS api_tokens (
+  `id` VARCHAR(64) NOT NULL,
+  `token_hash` VARCHAR(128) NOT NULL,
+  `user_id` VARCHAR(255) NOT NULL,
+  `name` VARCHAR(255) NOT NULL,
+  `scopes` TEXT NOT NULL,
+  `created_at` DATETIME NOT NULL,
+  `last_used_at` DATETIME NULL,
+  `expires` DATETIME NULL,
+  PRIMARY KEY (`id`),
+  KEY `api_tokens_user_id` (`user_id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS user_tokens (
+  `id` VARCHAR(64) NOT NULL,
+  `token_hash` VARCHAR(128) NOT NULL,
+  `user_id` VARCHAR(255) NOT NULL,
+  `purpose` VARCHAR(64) NOT NULL,
+  `created_at` DATETIME NOT NULL,
+  `expires` DATETIME NOT NULL,
+  PRIMARY KEY (`id`),
+  UNIQUE KEY `user_tokens_user_id_purpose` (`user_id`, `purpose`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS sessions (
+  id VARCHAR(128) NOT NULL PRIMARY KEY,
+  expires TIMESTAMPTZ NOT NULL,
+  session TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS sessions_expires ON sessions (expires);
@@ -0,0 +1,8 @@
+ALTER TABLE sessions
+  ADD COLUMN user_id VARCHAR(255) NULL,
+  ADD COLUMN created_at TIMESTAMPTZ NULL,
+  ADD COLUMN last_seen TIMESTAMPTZ NULL,
+  ADD COLUMN user_agent TEXT NULL,
+  ADD COLUMN ip VARCHAR(64) NULL;
+
+CREATE INDEX sessions_user_id ON sessions (user_id);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS remember_tokens (
+  selector VARCHAR(64) NOT NULL PRIMARY KEY,
+  validator_hash VARCHAR(128) NOT NULL,
+  previous_hash VARCHAR(128) NULL,
+  rotated_at TIMESTAMPTZ NULL,
+  user_id VARCHAR(255) NOT NULL,
+  expires TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS remember_tokens_user_id ON remember_tokens (user_id);
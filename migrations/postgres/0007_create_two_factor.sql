@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS two_factor (
+  user_id VARCHAR(255) NOT NULL PRIMARY KEY,
+  secret VARCHAR(128) NOT NULL,
+  confirmed_at TIMESTAMPTZ NULL,
+  last_step BIGINT NULL,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  attempts_since TIMESTAMPTZ NULL
+);
+
+CREATE TABLE IF NOT EXISTS two_factor_recovery_codes (
+  user_id VARCHAR(255) NOT NULL,
+  code_hash VARCHAR(128) NOT NULL,
+  PRIMARY KEY (user_id, code_hash)
+);
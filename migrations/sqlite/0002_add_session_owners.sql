@@ -0,0 +1,7 @@
+ALTER TABLE sessions ADD COLUMN user_id VARCHAR(255) NULL;
+ALTER TABLE sessions ADD COLUMN created_at DATETIME NULL;
+ALTER TABLE sessions ADD COLUMN last_seen DATETIME NULL;
+ALTER TABLE sessions ADD COLUMN user_agent TEXT NULL;
+ALTER TABLE sessions ADD COLUMN ip VARCHAR(64) NULL;
+
+CREATE INDEX sessions_user_id ON sessions (user_id);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS user_tokens (
+  id VARCHAR(64) NOT NULL PRIMARY KEY,
+  token_hash VARCHAR(128) NOT NULL,
+  user_id VARCHAR(255) NOT NULL,
+  purpose VARCHAR(64) NOT NULL,
+  created_at DATETIME NOT NULL,
+  expires DATETIME NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS user_tokens_user_id_purpose ON user_tokens (user_id, purpose);
@@ -0,0 +1,120 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+};
+
+use super::{middleware::wants_json, Auth, Authenticatable};
+use crate::{errors::ErrResponse, sessions::UserSession};
+
+/// the logged in user, guests get redirected to `AuthConfig::login_url`,
+/// or get a 401 if the request was made from javascript.
+/// users who still have to pass two-factor are redirected to `AuthConfig::two_factor_url`
+pub struct AuthUser<U>(pub U);
+
+/// the logged in user, or `None` for guests
+pub struct MaybeAuthUser<U>(pub Option<U>);
+
+pub enum AuthRejection {
+    /// the user isn't logged in
+    Redirect(String),
+    /// the user isn't logged in, and the request was made from javascript
+    Unauthorized,
+    Error(ErrResponse),
+}
+
+impl IntoResponse for AuthRejection {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Redirect(url) => Redirect::to(&url).into_response(),
+            Self::Unauthorized => StatusCode::UNAUTHORIZED.into_response(),
+            Self::Error(err) => err.into_response(),
+        }
+    }
+}
+
+impl From<ErrResponse> for AuthRejection {
+    fn from(err: ErrResponse) -> Self {
+        Self::Error(err)
+    }
+}
+
+/// the user is only loaded once per request, even if several extractors ask for it
+#[derive(Clone)]
+struct LoadedUser<U>(Option<U>);
+
+pub(super) fn auth<U: Authenticatable>(parts: &Parts) -> Result<Auth<U>, ErrResponse> {
+    parts.extensions.get::<Auth<U>>().cloned().ok_or_else(|| {
+        ErrResponse::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "`Auth` extension missing",
+        )
+    })
+}
+
+/// what guests get instead of the page, users waiting on two-factor are sent to enter their code
+pub(super) fn guest_rejection<U: Authenticatable>(parts: &Parts, auth: &Auth<U>) -> AuthRejection {
+    if wants_json(&parts.headers) {
+        return AuthRejection::Unauthorized;
+    }
+
+    let pending = parts
+        .extensions
+        .get::<UserSession>()
+        .is_some_and(|session| session.two_factor_pending().is_some());
+    if pending {
+        AuthRejection::Redirect(auth.config().two_factor_url.clone())
+    } else {
+        AuthRejection::Redirect(auth.config().login_url.clone())
+    }
+}
+
+pub(super) async fn load_user<U: Authenticatable>(
+    parts: &mut Parts,
+    auth: &Auth<U>,
+) -> Result<Option<U>, ErrResponse> {
+    if let Some(LoadedUser(user)) = parts.extensions.get::<LoadedUser<U>>() {
+        return Ok(user.clone());
+    }
+
+    let session = parts
+        .extensions
+        .get::<UserSession>()
+        .expect("`session_middleware` missing");
+    let user = auth.user(session).await?;
+
+    parts.extensions.insert(LoadedUser(user.clone()));
+    Ok(user)
+}
+
+#[async_trait]
+impl<U, S> FromRequestParts<S> for AuthUser<U>
+where
+    U: Authenticatable,
+    S: Send + Sync,
+{
+    type Rejection = AuthRejection;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let auth = auth::<U>(parts)?;
+        match load_user(parts, &auth).await? {
+            Some(user) => Ok(Self(user)),
+            None => Err(guest_rejection(parts, &auth)),
+        }
+    }
+}
+
+#[async_trait]
+impl<U, S> FromRequestParts<S> for MaybeAuthUser<U>
+where
+    U: Authenticatable,
+    S: Send + Sync,
+{
+    type Rejection = ErrResponse;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let auth = auth::<U>(parts)?;
+        Ok(Self(load_user(parts, &auth).await?))
+    }
+}
@@ -0,0 +1,94 @@
+use axum::{
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        HeaderMap, Method, Request,
+    },
+    middleware::Next,
+    response::{Redirect, Response},
+};
+use std::fmt::Display;
+
+use super::{
+    extractors::{auth, guest_rejection, load_user},
+    AuthRejection, Authenticatable,
+};
+use crate::{
+    errors::ErrResponse,
+    sessions::{SessionKey, UserSession},
+};
+
+/// where the user was going before being sent to the login page
+const INTENDED_KEY: SessionKey<String> = SessionKey::new("internal-key-intended");
+
+/// returns true for requests made from javascript, which shouldn't get redirected
+pub(crate) fn wants_json(headers: &HeaderMap) -> bool {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+    };
+
+    header("x-requested-with").eq_ignore_ascii_case("xmlhttprequest")
+        || header(ACCEPT.as_str()).contains("application/json")
+        || header(CONTENT_TYPE.as_str()).starts_with("application/json")
+}
+
+/// only lets logged in users through
+///
+/// guests are redirected to `AuthConfig::login_url`, and the url they wanted is stored
+/// so that `redirect_intended` can send them back after logging in.
+/// requests made from javascript get a 401 instead
+///
+/// ```ignore
+/// Router::new()
+///     .route("/settings", get(settings))
+///     .route_layer(axum::middleware::from_fn(require_auth::<User, _>))
+/// ```
+pub async fn require_auth<U, B>(req: Request<B>, next: Next<B>) -> Result<Response, AuthRejection>
+where
+    U: Authenticatable,
+{
+    let (mut parts, body) = req.into_parts();
+    let auth = auth::<U>(&parts)?;
+
+    if load_user(&mut parts, &auth).await?.is_some() {
+        return Ok(next.run(Request::from_parts(parts, body)).await);
+    }
+
+    if wants_json(&parts.headers) {
+        return Err(AuthRejection::Unauthorized);
+    }
+
+    // only pages are worth going back to, not form submissions
+    if parts.method == Method::GET {
+        let mut session = parts
+            .extensions
+            .get::<UserSession>()
+            .expect("`session_middleware` missing")
+            .clone();
+        let intended = parts
+            .uri
+            .path_and_query()
+            .map(|p| p.to_string())
+            .unwrap_or_else(|| "/".to_string());
+        session.insert_typed(INTENDED_KEY, &intended).await?;
+    }
+
+    Err(guest_rejection(&parts, &auth))
+}
+
+/// redirects to the page `require_auth` sent the user away from, or to `default`
+/// usually called right after `login`
+pub async fn redirect_intended(
+    session: &mut UserSession,
+    default: impl Display,
+) -> Result<Redirect, ErrResponse> {
+    let url = session
+        .take(INTENDED_KEY)
+        .await?
+        // it always comes from our own request uri, but just in case
+        .filter(|url| url.starts_with('/') && !url.starts_with("//"))
+        .unwrap_or_else(|| default.to_string());
+    Ok(Redirect::to(&url))
+}
@@ -0,0 +1,607 @@
+//! logging users in and out, on top of `UserSession`
+//!
+//! the app implements `UserProvider` for its user type, and registers it with `Auth`:
+//!
+//! ```ignore
+//! Extension(Auth::new(Users { pool: pool.clone() }).with_login_route(route_login()))
+//! ```
+//!
+//! handlers then take `AuthUser<User>`, which redirects guests to the login page,
+//! or `MaybeAuthUser<User>`. whole routers can be guarded with `require_auth`
+//!
+//! what users are allowed to do is decided by a `Policy`, registered with `Auth::with_policy`,
+//! and checked with `Can<Action>`, `require_can`, or `Auth::authorize`
+//!
+//! users with two-factor enabled (see `TwoFactor`) are left pending by `Auth::attempt`,
+//! and aren't logged in until they pass `Auth::complete_two_factor`
+//!
+//! email verification and password reset links are made and checked with `UserTokens`
+//!
+//! json apis used by scripts authenticate with `ApiTokens` and `BearerToken` instead of the session
+
+use axum::{async_trait, http::StatusCode};
+use std::{fmt::Display, sync::Arc};
+
+use crate::{errors::ErrResponse, sessions::UserSession};
+
+mod extractors;
+mod middleware;
+mod password;
+mod policy;
+mod tokens;
+mod two_factor;
+mod user_tokens;
+
+pub use extractors::{AuthRejection, AuthUser, MaybeAuthUser};
+pub use middleware::{redirect_intended, require_auth};
+pub use password::{
+    hash_password, hash_password_blocking, verify_password, verify_password_blocking,
+};
+pub use policy::{require_can, Abilities, Action, Can, HasRoles, Policy, RolePolicy};
+pub use tokens::{ApiToken, ApiTokens, BearerToken, NewApiToken};
+#[cfg(feature = "qr_code")]
+pub use two_factor::qr_code;
+pub use two_factor::{Totp, TwoFactor, TwoFactorSetup};
+pub use user_tokens::{TokenPurpose, TokenQuery, UserTokens, TOKEN_PARAM};
+
+pub(crate) use tokens::bearer_token;
+
+/// a user that can log in
+pub trait Authenticatable: Clone + Send + Sync + 'static {
+    /// stored in the session, and passed back to `UserProvider::find_by_id`
+    fn auth_id(&self) -> String;
+}
+
+/// loads users for `Auth`, usually from the database
+#[async_trait]
+pub trait UserProvider<U: Authenticatable>: Send + Sync + 'static {
+    /// the user with the id returned by `Authenticatable::auth_id`
+    async fn find_by_id(&self, id: &str) -> Result<Option<U>, ErrResponse>;
+
+    /// the user with these credentials, eg email and password
+    /// use `verify_password` to check the password against the stored hash
+    async fn find_by_credentials(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<U>, ErrResponse>;
+}
+
+/// where guests are sent by `AuthUser`
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    pub login_url: String,
+    /// where users who still have to enter their two-factor code are sent
+    pub two_factor_url: String,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            login_url: "/login".to_string(),
+            two_factor_url: "/two-factor".to_string(),
+        }
+    }
+}
+
+/// the `UserProvider` for `U`, added as an extension
+pub struct Auth<U> {
+    provider: Arc<dyn UserProvider<U>>,
+    policy: Option<Arc<dyn Policy<U>>>,
+    two_factor: Option<TwoFactor>,
+    config: AuthConfig,
+}
+
+impl<U> Clone for Auth<U> {
+    fn clone(&self) -> Self {
+        Self {
+            provider: self.provider.clone(),
+            policy: self.policy.clone(),
+            two_factor: self.two_factor.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+impl<U: Authenticatable> Auth<U> {
+    #[must_use]
+    pub fn new(provider: impl UserProvider<U>) -> Self {
+        Self {
+            provider: Arc::new(provider),
+            policy: None,
+            two_factor: None,
+            config: AuthConfig::default(),
+        }
+    }
+
+    pub fn with_config(mut self, config: AuthConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// where guests are redirected, usually a `NamedRoute`
+    pub fn with_login_route(mut self, route: impl Display) -> Self {
+        self.config.login_url = route.to_string();
+        self
+    }
+
+    /// where users who still have to enter their two-factor code are redirected
+    pub fn with_two_factor_route(mut self, route: impl Display) -> Self {
+        self.config.two_factor_url = route.to_string();
+        self
+    }
+
+    /// makes `attempt` ask users with two-factor enabled for a code
+    pub fn with_two_factor(mut self, two_factor: TwoFactor) -> Self {
+        self.two_factor = Some(two_factor);
+        self
+    }
+
+    pub fn two_factor(&self) -> Option<&TwoFactor> {
+        self.two_factor.as_ref()
+    }
+
+    /// decides what users can do, see `Can`
+    pub fn with_policy(mut self, policy: impl Policy<U>) -> Self {
+        self.policy = Some(Arc::new(policy));
+        self
+    }
+
+    pub fn config(&self) -> &AuthConfig {
+        &self.config
+    }
+
+    pub fn provider(&self) -> &dyn UserProvider<U> {
+        self.provider.as_ref()
+    }
+
+    /// the user logged in to `session`, if any
+    pub async fn user(&self, session: &UserSession) -> Result<Option<U>, ErrResponse> {
+        match session.user_id() {
+            Some(id) => self.provider.find_by_id(&id).await,
+            None => Ok(None),
+        }
+    }
+
+    /// whether `user` can do `action` on `resource`
+    /// returns an error if there's no policy
+    pub async fn allows(
+        &self,
+        user: &U,
+        action: &str,
+        resource: Option<&str>,
+    ) -> Result<bool, ErrResponse> {
+        let policy = self.policy.as_ref().ok_or_else(|| {
+            ErrResponse::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "no `Policy` registered with `Auth`",
+            )
+        })?;
+        policy.allows(user, action, resource).await
+    }
+
+    /// same as `allows`, but returns a 403 if the user can't
+    ///
+    /// ```ignore
+    /// auth.authorize(&user, "posts.edit", Some(&post_id.to_string())).await?;
+    /// ```
+    pub async fn authorize(
+        &self,
+        user: &U,
+        action: &str,
+        resource: Option<&str>,
+    ) -> Result<(), ErrResponse> {
+        if self.allows(user, action, resource).await? {
+            Ok(())
+        } else {
+            Err(policy::forbidden())
+        }
+    }
+
+    /// checks several actions at once, for `HtmlContext::with_abilities`
+    /// guests can't do anything
+    pub async fn abilities(
+        &self,
+        user: Option<&U>,
+        actions: &[(&str, Option<&str>)],
+    ) -> Result<Abilities, ErrResponse> {
+        let mut abilities = Abilities::default();
+        for &(action, resource) in actions {
+            let allowed = match user {
+                Some(user) => self.allows(user, action, resource).await?,
+                None => false,
+            };
+            abilities.insert(action, resource, allowed);
+        }
+        Ok(abilities)
+    }
+
+    /// logs in the user with these credentials, and returns it
+    /// returns `None` if the credentials are wrong
+    ///
+    /// if the user has two-factor enabled, they're only marked as pending,
+    /// and have to be sent to `AuthConfig::two_factor_url`.
+    /// check for it with `UserSession::two_factor_pending`
+    pub async fn attempt(
+        &self,
+        session: &mut UserSession,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<U>, ErrResponse> {
+        let user = self
+            .provider
+            .find_by_credentials(username, password)
+            .await?;
+        let user = match user {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+
+        match &self.two_factor {
+            Some(two_factor) if two_factor.is_enabled(&user.auth_id()).await? => {
+                // whoever was logged in before isn't anymore
+                session.regenerate().await?;
+                session.remove_user_id().await?;
+                session.set_two_factor_pending(user.auth_id()).await?;
+            }
+            _ => login(session, &user).await?,
+        }
+        Ok(Some(user))
+    }
+
+    /// logs in the pending user if `code` is right, and returns it
+    /// `code` can be from the authenticator app, or a recovery code
+    ///
+    /// returns a 429 after too many wrong codes, see `TwoFactor::verify`
+    pub async fn complete_two_factor(
+        &self,
+        session: &mut UserSession,
+        code: &str,
+    ) -> Result<Option<U>, ErrResponse> {
+        let two_factor = self.two_factor.as_ref().ok_or_else(|| {
+            ErrResponse::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "no `TwoFactor` registered with `Auth`",
+            )
+        })?;
+        let user_id = match session.two_factor_pending() {
+            Some(user_id) => user_id,
+            None => return Ok(None),
+        };
+        if !two_factor.verify(&user_id, code).await? {
+            return Ok(None);
+        }
+
+        let user = self.provider.find_by_id(&user_id).await?;
+        if let Some(user) = &user {
+            login(session, user).await?;
+        }
+        Ok(user)
+    }
+}
+
+/// logs `user` in
+///
+/// the session gets a new id and csrf token, to prevent session fixation
+pub async fn login<U: Authenticatable>(
+    session: &mut UserSession,
+    user: &U,
+) -> Result<(), ErrResponse> {
+    session.regenerate().await?;
+    session.regenerate_csrf_token().await?;
+    session.remove_two_factor_pending().await?;
+    session.set_user_id(user.auth_id()).await
+}
+
+/// logs the user out, destroying the whole session
+pub async fn logout(session: &mut UserSession) -> Result<(), ErrResponse> {
+    session.destroy().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sessions::{session_middleware, MemoryStore},
+        tests::helpers::*,
+    };
+    use axum::{
+        extract::Extension,
+        http::{header::LOCATION, HeaderValue, StatusCode},
+        routing::get,
+        Router,
+    };
+
+    #[derive(Clone)]
+    struct User {
+        id: u32,
+        name: &'static str,
+    }
+
+    impl Authenticatable for User {
+        fn auth_id(&self) -> String {
+            self.id.to_string()
+        }
+    }
+
+    impl HasRoles for User {
+        fn roles(&self) -> Vec<&str> {
+            vec!["editor"]
+        }
+    }
+
+    struct Users;
+
+    #[async_trait]
+    impl UserProvider<User> for Users {
+        async fn find_by_id(&self, id: &str) -> Result<Option<User>, ErrResponse> {
+            Ok((id == "1").then_some(User {
+                id: 1,
+                name: "annie",
+            }))
+        }
+
+        async fn find_by_credentials(
+            &self,
+            username: &str,
+            password: &str,
+        ) -> Result<Option<User>, ErrResponse> {
+            let hash = hash_password("hunter2").await?;
+            Ok(
+                (username == "annie" && verify_password(password, &hash).await).then_some(User {
+                    id: 1,
+                    name: "annie",
+                }),
+            )
+        }
+    }
+
+    fn auth() -> Auth<User> {
+        Auth::new(Users)
+            .with_login_route("/sign-in")
+            .with_policy(RolePolicy::new().grant("editor", ["posts.edit"]))
+    }
+
+    fn app(router: Router) -> Router {
+        app_with(router, auth())
+    }
+
+    fn app_with(router: Router, auth: Auth<User>) -> Router {
+        router
+            .route(
+                "/login/:password",
+                get(
+                    |mut session: Extension<UserSession>,
+                     auth: Extension<Auth<User>>,
+                     axum::extract::Path(password): axum::extract::Path<String>| async move {
+                        let user = auth.attempt(&mut session, "annie", &password).await?;
+                        Ok::<_, ErrResponse>(if user.is_some() { "ok" } else { "wrong" })
+                    },
+                ),
+            )
+            .route(
+                "/logout",
+                get(|mut session: Extension<UserSession>| async move {
+                    logout(&mut session).await?;
+                    Ok::<_, ErrResponse>("ok")
+                }),
+            )
+            .layer(axum::middleware::from_fn(
+                session_middleware::<MemoryStore, _>,
+            ))
+            .layer(Extension(MemoryStore::new().with_same_site(crate::cookies::SameSite::Lax)))
+            .layer(Extension(auth))
+    }
+
+    #[tokio::test]
+    async fn test_login_and_extractors() {
+        let app = app(Router::new()
+            .route(
+                "/profile",
+                get(|AuthUser(user): AuthUser<User>| async move { user.name }),
+            )
+            .route(
+                "/home",
+                get(|MaybeAuthUser(user): MaybeAuthUser<User>| async move {
+                    user.map(|u| u.name).unwrap_or("guest")
+                }),
+            ));
+
+        let res = app.clone().req(empty_get("/profile")).await;
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        assert_eq!(res.parts.headers[LOCATION], "/sign-in");
+        assert!(app
+            .clone()
+            .req(empty_get("/home"))
+            .await
+            .contains_str("guest"));
+
+        let res = app.clone().req(empty_get("/login/nope")).await;
+        assert!(res.contains_str("wrong"));
+
+        let res = app.clone().req(empty_get("/login/hunter2")).await;
+        assert!(res.contains_str("ok"));
+        let cookie = session_cookie(&res);
+
+        let res = app.clone().req(with_cookie("/profile", &cookie)).await;
+        assert!(res.contains_str("annie"));
+        let res = app.clone().req(with_cookie("/home", &cookie)).await;
+        assert!(res.contains_str("annie"));
+
+        app.clone().req(with_cookie("/logout", &cookie)).await;
+        let res = app.clone().req(with_cookie("/profile", &cookie)).await;
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    }
+
+    #[tokio::test]
+    async fn test_require_auth_and_intended() {
+        let app = app(Router::new()
+            .route("/settings", get(|| async { "settings" }))
+            .route_layer(axum::middleware::from_fn(require_auth::<User, _>))
+            .route(
+                "/continue",
+                get(|mut session: Extension<UserSession>| async move {
+                    redirect_intended(&mut session, "/home").await
+                }),
+            ));
+
+        let mut req = empty_get("/settings");
+        req.headers_mut().insert(
+            "x-requested-with",
+            HeaderValue::from_static("XMLHttpRequest"),
+        );
+        let res = app.clone().req(req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let res = app.clone().req(empty_get("/settings?tab=2")).await;
+        assert_eq!(res.parts.headers[LOCATION], "/sign-in");
+        let cookie = session_cookie(&res);
+
+        let res = app
+            .clone()
+            .req(with_cookie("/login/hunter2", &cookie))
+            .await;
+        let cookie = session_cookie(&res);
+
+        let res = app.clone().req(with_cookie("/settings", &cookie)).await;
+        assert!(res.contains_str("settings"));
+        let res = app.clone().req(with_cookie("/continue", &cookie)).await;
+        assert_eq!(res.parts.headers[LOCATION], "/settings?tab=2");
+
+        // it's only used once
+        let cookie = session_cookie(&res);
+        let res = app.clone().req(with_cookie("/continue", &cookie)).await;
+        assert_eq!(res.parts.headers[LOCATION], "/home");
+    }
+
+    struct EditPosts;
+    impl Action for EditPosts {
+        type User = User;
+        const NAME: &'static str = "posts.edit";
+    }
+
+    struct DeletePosts;
+    impl Action for DeletePosts {
+        type User = User;
+        const NAME: &'static str = "posts.delete";
+    }
+
+    #[tokio::test]
+    async fn test_policies() {
+        let app =
+            app(
+                Router::new()
+                    .route(
+                        "/edit",
+                        get(|can: Can<EditPosts>| async move { can.user.name }),
+                    )
+                    .route(
+                        "/delete",
+                        get(|can: Can<DeletePosts>| async move { can.user.name }),
+                    )
+                    .route(
+                        "/abilities",
+                        get(
+                            |MaybeAuthUser(user): MaybeAuthUser<User>,
+                             auth: Extension<Auth<User>>| async move {
+                                let abilities = auth
+                                    .abilities(
+                                        user.as_ref(),
+                                        &[("posts.edit", None), ("posts.delete", None)],
+                                    )
+                                    .await?;
+                                Ok::<_, ErrResponse>(format!(
+                                    "{} {}",
+                                    abilities.can("posts.edit", None),
+                                    abilities.can("posts.delete", None)
+                                ))
+                            },
+                        ),
+                    ),
+            );
+
+        let res = app.clone().req(empty_get("/edit")).await;
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        assert!(app
+            .clone()
+            .req(empty_get("/abilities"))
+            .await
+            .contains_str("false false"));
+
+        let res = app.clone().req(empty_get("/login/hunter2")).await;
+        let cookie = session_cookie(&res);
+
+        let res = app.clone().req(with_cookie("/edit", &cookie)).await;
+        assert!(res.contains_str("annie"));
+        let res = app.clone().req(with_cookie("/delete", &cookie)).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = app.clone().req(with_cookie("/abilities", &cookie)).await;
+        assert!(res.contains_str("true false"));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_two_factor_login() {
+        let two_factor = TwoFactor::new(crate::db::test_pool().await, "muxa");
+        let setup = two_factor.enable("1", "annie").await.unwrap();
+        let totp = Totp::from_secret(&setup.secret).unwrap();
+        let step = Totp::current_step();
+        two_factor
+            .confirm("1", &totp.code_at(step))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let app = app_with(
+            Router::new()
+                .route(
+                    "/profile",
+                    get(|AuthUser(user): AuthUser<User>| async move { user.name }),
+                )
+                .route(
+                    "/two-factor/:code",
+                    get(
+                        |mut session: Extension<UserSession>,
+                         auth: Extension<Auth<User>>,
+                         axum::extract::Path(code): axum::extract::Path<String>| async move {
+                            let user = auth.complete_two_factor(&mut session, &code).await?;
+                            Ok::<_, ErrResponse>(if user.is_some() { "ok" } else { "wrong" })
+                        },
+                    ),
+                ),
+            auth().with_two_factor(two_factor),
+        );
+
+        let res = app.clone().req(empty_get("/login/hunter2")).await;
+        assert!(res.contains_str("ok"));
+        let cookie = session_cookie(&res);
+
+        // the password was right, but the code is still missing
+        let res = app.clone().req(with_cookie("/profile", &cookie)).await;
+        assert_eq!(res.parts.headers[LOCATION], "/two-factor");
+        let res = app
+            .clone()
+            .req(with_cookie("/two-factor/000000", &cookie))
+            .await;
+        assert!(res.contains_str("wrong"));
+
+        let code = totp.code_at(step + 1);
+        let res = app
+            .clone()
+            .req(with_cookie(&format!("/two-factor/{code}"), &cookie))
+            .await;
+        assert!(res.contains_str("ok"));
+        let cookie = session_cookie(&res);
+        let res = app.clone().req(with_cookie("/profile", &cookie)).await;
+        assert!(res.contains_str("annie"));
+
+        // logging in again logs out until the code is entered
+        let res = app
+            .clone()
+            .req(with_cookie("/login/hunter2", &cookie))
+            .await;
+        let cookie = session_cookie(&res);
+        let res = app.clone().req(with_cookie("/profile", &cookie)).await;
+        assert_eq!(res.parts.headers[LOCATION], "/two-factor");
+    }
+}
@@ -0,0 +1,79 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::http::StatusCode;
+
+use crate::errors::ErrResponse;
+
+/// hashes `password` with argon2id and a random salt
+/// the result contains the parameters and salt, so it's all that needs to be stored
+///
+/// argon2 is slow on purpose, so this runs on tokio's blocking threads
+pub async fn hash_password(password: &str) -> Result<String, ErrResponse> {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || hash_password_blocking(&password))
+        .await
+        .map_err(|err| {
+            ErrResponse::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("couldn't hash password: {err}"),
+            )
+        })?
+}
+
+/// returns true if `password` matches `hash`, as returned by `hash_password`
+///
+/// malformed hashes never match. like `hash_password`, this runs on the blocking threads
+pub async fn verify_password(password: &str, hash: &str) -> bool {
+    let (password, hash) = (password.to_string(), hash.to_string());
+    tokio::task::spawn_blocking(move || verify_password_blocking(&password, &hash))
+        .await
+        .unwrap_or_else(|err| {
+            tracing::error!("couldn't verify password: {err}");
+            false
+        })
+}
+
+/// same as `hash_password`, for code that isn't async
+pub fn hash_password_blocking(password: &str) -> Result<String, ErrResponse> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|err| {
+            ErrResponse::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("couldn't hash password: {err}"),
+            )
+        })?;
+    Ok(hash.to_string())
+}
+
+/// same as `verify_password`, for code that isn't async
+pub fn verify_password_blocking(password: &str, hash: &str) -> bool {
+    let hash = match PasswordHash::new(hash) {
+        Ok(hash) => hash,
+        Err(err) => {
+            tracing::error!("malformed password hash: {err}");
+            return false;
+        }
+    };
+    Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_hash_and_verify() {
+        let hash = hash_password("hunter2").await.unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("hunter2", &hash).await);
+        assert!(!verify_password("hunter3", &hash).await);
+        assert!(!verify_password("hunter2", "not a hash").await);
+        assert!(verify_password_blocking("hunter2", &hash));
+    }
+}
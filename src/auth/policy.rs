@@ -0,0 +1,186 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    marker::PhantomData,
+};
+
+use super::{
+    extractors::{auth, guest_rejection, load_user},
+    AuthRejection, Authenticatable,
+};
+use crate::errors::ErrResponse;
+
+/// decides what users are allowed to do, registered with `Auth::with_policy`
+///
+/// `action` is a name like `"posts.edit"`, and `resource` is what it's done to,
+/// usually an id, or `None` for actions that aren't on anything in particular
+#[async_trait]
+pub trait Policy<U>: Send + Sync + 'static {
+    async fn allows(
+        &self,
+        user: &U,
+        action: &str,
+        resource: Option<&str>,
+    ) -> Result<bool, ErrResponse>;
+}
+
+/// a user with roles, for `RolePolicy`
+pub trait HasRoles {
+    fn roles(&self) -> Vec<&str>;
+}
+
+/// allows actions based on the user's roles, ignoring the resource
+///
+/// ```ignore
+/// RolePolicy::new()
+///     .grant("admin", ["posts.edit", "posts.delete"])
+///     .grant("editor", ["posts.edit"])
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RolePolicy {
+    permissions: HashMap<String, HashSet<String>>,
+}
+
+impl RolePolicy {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// lets users with `role` do `actions`
+    pub fn grant<I>(mut self, role: impl ToString, actions: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: ToString,
+    {
+        self.permissions
+            .entry(role.to_string())
+            .or_default()
+            .extend(actions.into_iter().map(|a| a.to_string()));
+        self
+    }
+
+    pub fn role_allows(&self, role: &str, action: &str) -> bool {
+        self.permissions
+            .get(role)
+            .is_some_and(|actions| actions.contains(action))
+    }
+}
+
+#[async_trait]
+impl<U> Policy<U> for RolePolicy
+where
+    U: HasRoles + Send + Sync + 'static,
+{
+    async fn allows(&self, user: &U, action: &str, _: Option<&str>) -> Result<bool, ErrResponse> {
+        Ok(user
+            .roles()
+            .into_iter()
+            .any(|role| self.role_allows(role, action)))
+    }
+}
+
+/// an action that `Can` and `require_can` check
+///
+/// ```ignore
+/// struct EditPosts;
+/// impl Action for EditPosts {
+///     type User = User;
+///     const NAME: &'static str = "posts.edit";
+/// }
+/// ```
+pub trait Action: Send + Sync + 'static {
+    type User: Authenticatable;
+
+    /// passed to `Policy::allows`
+    const NAME: &'static str;
+
+    /// the resource passed to `Policy::allows`, `None` by default
+    /// checks that depend on path params are usually easier with `Auth::authorize`
+    fn resource(_parts: &Parts) -> Option<String> {
+        None
+    }
+}
+
+/// the results of checking some actions, for templates, which can't wait on the policy
+/// see `Auth::abilities` and `HtmlContext::can`
+#[derive(Debug, Clone, Default)]
+pub struct Abilities(HashMap<(String, Option<String>), bool>);
+
+impl Abilities {
+    pub fn insert(&mut self, action: &str, resource: Option<&str>, allowed: bool) {
+        self.0.insert(
+            (action.to_string(), resource.map(ToString::to_string)),
+            allowed,
+        );
+    }
+
+    /// false for actions that weren't checked
+    pub fn can(&self, action: &str, resource: Option<&str>) -> bool {
+        self.0
+            .get(&(action.to_string(), resource.map(ToString::to_string)))
+            .copied()
+            .unwrap_or(false)
+    }
+}
+
+#[track_caller]
+pub(super) fn forbidden() -> ErrResponse {
+    ErrResponse::new(StatusCode::FORBIDDEN, "this action is not allowed")
+}
+
+/// the logged in user, if they can do `A`
+///
+/// guests are rejected like with `AuthUser`, and users who can't get a 403
+pub struct Can<A: Action> {
+    pub user: A::User,
+    action: PhantomData<A>,
+}
+
+async fn check<A: Action>(parts: &mut Parts) -> Result<A::User, AuthRejection> {
+    let auth = auth::<A::User>(parts)?;
+    let user = match load_user(parts, &auth).await? {
+        Some(user) => user,
+        None => return Err(guest_rejection(parts, &auth)),
+    };
+
+    let resource = A::resource(parts);
+    auth.authorize(&user, A::NAME, resource.as_deref()).await?;
+    Ok(user)
+}
+
+#[async_trait]
+impl<A, S> FromRequestParts<S> for Can<A>
+where
+    A: Action,
+    S: Send + Sync,
+{
+    type Rejection = AuthRejection;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self {
+            user: check::<A>(parts).await?,
+            action: PhantomData,
+        })
+    }
+}
+
+/// same as `Can`, for whole routers
+///
+/// ```ignore
+/// .route_layer(axum::middleware::from_fn(require_can::<EditPosts, _>))
+/// ```
+pub async fn require_can<A, B>(req: Request<B>, next: Next<B>) -> Result<Response, AuthRejection>
+where
+    A: Action,
+{
+    let (mut parts, body) = req.into_parts();
+    check::<A>(&mut parts).await?;
+    Ok(next.run(Request::from_parts(parts, body)).await)
+}
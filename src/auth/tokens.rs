@@ -0,0 +1,324 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header::AUTHORIZATION, request::Parts, StatusCode},
+};
+use chrono::{DateTime, Utc};
+
+use super::AuthRejection;
+use crate::{
+    db::{sql, DbPool},
+    errors::ErrResponse,
+    helpers::{constant_time_eq, hash_token, random_token},
+};
+
+/// personal access tokens, for scripts using the app's json apis
+///
+/// tokens look like `id.secret`, and only a hash of the secret is stored,
+/// in the `api_tokens` table (see `muxa::db::migrate`).
+/// add it as an extension, and take `BearerToken` in api handlers
+#[derive(Clone)]
+pub struct ApiTokens {
+    pool: DbPool,
+}
+
+/// a stored token, without the secret
+#[derive(Debug, Clone)]
+pub struct ApiToken {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl ApiToken {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+
+    /// returns a 403 if the token doesn't have `scope`
+    #[track_caller]
+    pub fn require_scope(&self, scope: &str) -> Result<(), ErrResponse> {
+        if self.has_scope(scope) {
+            Ok(())
+        } else {
+            Err(ErrResponse::new(
+                StatusCode::FORBIDDEN,
+                format!("token is missing the `{scope}` scope"),
+            ))
+        }
+    }
+}
+
+/// a token that was just created
+#[derive(Debug, Clone)]
+pub struct NewApiToken {
+    /// the whole token, to show to the user
+    /// it can't be recovered later, so this is the only chance
+    pub plaintext: String,
+    pub token: ApiToken,
+}
+
+#[derive(sqlx::FromRow)]
+struct Row {
+    id: String,
+    token_hash: String,
+    user_id: String,
+    name: String,
+    scopes: String,
+    created_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+    expires: Option<DateTime<Utc>>,
+}
+
+impl From<Row> for ApiToken {
+    fn from(row: Row) -> Self {
+        Self {
+            id: row.id,
+            user_id: row.user_id,
+            name: row.name,
+            scopes: row.scopes.split_whitespace().map(String::from).collect(),
+            created_at: row.created_at,
+            last_used_at: row.last_used_at,
+            expires: row.expires,
+        }
+    }
+}
+
+const COLUMNS: &str = "id, token_hash, user_id, name, scopes, created_at, last_used_at, expires";
+
+impl ApiTokens {
+    #[must_use]
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    /// creates a token for the user, `name` is for telling tokens apart in the ui
+    ///
+    /// scopes are stored separated by spaces, so empty ones and ones with whitespace
+    /// are rejected with a 422
+    pub async fn create(
+        &self,
+        user_id: &str,
+        name: &str,
+        scopes: &[&str],
+        expires: Option<DateTime<Utc>>,
+    ) -> Result<NewApiToken, ErrResponse> {
+        if let Some(scope) = scopes
+            .iter()
+            .find(|s| s.is_empty() || s.contains(char::is_whitespace))
+        {
+            return Err(ErrResponse::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("invalid scope `{scope}`"),
+            ));
+        }
+
+        let id = random_token(12);
+        let secret = random_token(32);
+        let token = ApiToken {
+            id: id.clone(),
+            user_id: user_id.to_string(),
+            name: name.to_string(),
+            scopes: scopes.iter().map(ToString::to_string).collect(),
+            created_at: Utc::now(),
+            last_used_at: None,
+            expires,
+        };
+
+        let q = sql("INSERT INTO api_tokens
+            (id, token_hash, user_id, name, scopes, created_at, expires)
+            VALUES (?, ?, ?, ?, ?, ?, ?)");
+        sqlx::query(&q)
+            .bind(&token.id)
+            .bind(hash_token(&secret))
+            .bind(&token.user_id)
+            .bind(&token.name)
+            .bind(token.scopes.join(" "))
+            .bind(token.created_at)
+            .bind(token.expires)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(NewApiToken {
+            plaintext: format!("{id}.{secret}"),
+            token,
+        })
+    }
+
+    /// the token, if it exists and hasn't expired, and marks it as used
+    pub async fn verify(&self, plaintext: &str) -> Result<Option<ApiToken>, ErrResponse> {
+        let (id, secret) = match plaintext.split_once('.') {
+            Some(parts) => parts,
+            None => return Ok(None),
+        };
+
+        let q = format!("SELECT {COLUMNS} FROM api_tokens WHERE id = ?");
+        let q = sql(&q);
+        let row: Option<Row> = sqlx::query_as(&q)
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        let row = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        if !constant_time_eq(hash_token(secret).as_bytes(), row.token_hash.as_bytes())
+            || row.expires.is_some_and(|expires| expires < Utc::now())
+        {
+            return Ok(None);
+        }
+
+        let now = Utc::now();
+        sqlx::query(&sql("UPDATE api_tokens SET last_used_at = ? WHERE id = ?"))
+            .bind(now)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        let mut token = ApiToken::from(row);
+        token.last_used_at = Some(now);
+        Ok(Some(token))
+    }
+
+    /// the user's tokens, newest first
+    pub async fn tokens_for_user(&self, user_id: &str) -> Result<Vec<ApiToken>, ErrResponse> {
+        let q =
+            format!("SELECT {COLUMNS} FROM api_tokens WHERE user_id = ? ORDER BY created_at DESC");
+        let q = sql(&q);
+        let rows: Vec<Row> = sqlx::query_as(&q)
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(ApiToken::from).collect())
+    }
+
+    /// deletes one of the user's tokens, returns false if it didn't exist
+    ///
+    /// `user_id` is checked so that users can't revoke other people's tokens
+    pub async fn revoke(&self, user_id: &str, id: &str) -> Result<bool, ErrResponse> {
+        let q = sql("DELETE FROM api_tokens WHERE id = ? AND user_id = ?");
+        let result = sqlx::query(&q)
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// deletes all of the user's tokens, returns how many were deleted
+    pub async fn revoke_all(&self, user_id: &str) -> Result<u64, ErrResponse> {
+        let result = sqlx::query(&sql("DELETE FROM api_tokens WHERE user_id = ?"))
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+/// the token in the `Authorization: Bearer ...` header, requests without a valid one get a 401
+///
+/// check scopes with `ApiToken::require_scope`
+pub struct BearerToken(pub ApiToken);
+
+/// the token in the `Authorization` header, if it's a bearer token
+pub(crate) fn bearer_token(parts: &Parts) -> Option<&str> {
+    let header = parts.headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = header.split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then_some(token.trim())
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for BearerToken
+where
+    S: Send + Sync,
+{
+    type Rejection = AuthRejection;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let tokens = parts
+            .extensions
+            .get::<ApiTokens>()
+            .expect("`ApiTokens` extension missing");
+        let token = match bearer_token(parts) {
+            Some(token) => tokens.verify(token).await?,
+            None => None,
+        };
+        token.map(Self).ok_or(AuthRejection::Unauthorized)
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::{db::test_pool, tests::helpers::*};
+    use axum::{extract::Extension, http::HeaderValue, routing::get, Router};
+    use chrono::Duration;
+
+    #[tokio::test]
+    async fn test_invalid_scopes() {
+        let tokens = ApiTokens::new(test_pool().await);
+        for scope in ["read admin", "", "read\tadmin"] {
+            assert!(tokens.create("1", "script", &[scope], None).await.is_err());
+        }
+        assert!(tokens.tokens_for_user("1").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_create_verify_and_revoke() {
+        let tokens = ApiTokens::new(test_pool().await);
+        let new = tokens
+            .create("1", "deploy script", &["posts:read"], None)
+            .await
+            .unwrap();
+        let expired = tokens
+            .create("1", "old", &[], Some(Utc::now() - Duration::days(1)))
+            .await
+            .unwrap();
+
+        let app = Router::new()
+            .route(
+                "/posts",
+                get(|BearerToken(token): BearerToken| async move {
+                    token.require_scope("posts:read")?;
+                    token.require_scope("posts:write")?;
+                    Ok::<_, ErrResponse>(token.name)
+                }),
+            )
+            .layer(Extension(tokens.clone()));
+        let req = |token: &str| {
+            let mut req = empty_get("/posts");
+            req.headers_mut().insert(
+                AUTHORIZATION,
+                HeaderValue::from_str(&format!("Bearer {token}")).unwrap(),
+            );
+            req
+        };
+
+        let res = app.clone().req(empty_get("/posts")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = app.clone().req(req(&expired.plaintext)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let tampered = format!("{}.nope", new.token.id);
+        let res = app.clone().req(req(&tampered)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        // valid, but missing a scope
+        let res = app.clone().req(req(&new.plaintext)).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let listed = tokens.tokens_for_user("1").await.unwrap();
+        assert_eq!(listed.len(), 2);
+        assert!(listed.iter().any(|t| t.last_used_at.is_some()));
+
+        assert!(!tokens.revoke("2", &new.token.id).await.unwrap());
+        assert!(tokens.revoke("1", &new.token.id).await.unwrap());
+        let res = app.clone().req(req(&new.plaintext)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,556 @@
+use axum::http::StatusCode;
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+use crate::{
+    db::{sql, DbPool},
+    errors::ErrResponse,
+    helpers::{hash_token, random_bytes},
+};
+
+/// seconds each code lasts
+const PERIOD: i64 = 30;
+const DIGITS: u32 = 6;
+/// codes from this many periods before and after the current one are accepted,
+/// in case the phone's clock is a bit off
+const SKEW: i64 = 1;
+const RECOVERY_CODES: usize = 8;
+/// wrong codes allowed in `ATTEMPTS_WINDOW_MINUTES`, after that `verify` returns a 429
+const MAX_ATTEMPTS: i32 = 5;
+const ATTEMPTS_WINDOW_MINUTES: i64 = 15;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// rfc 4648 base32 without padding, which is what authenticator apps expect
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    out
+}
+
+/// ignores case, spaces and padding, since people type secrets in by hand
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in s.bytes().filter(|c| !matches!(c, b' ' | b'=')) {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// rfc 6238 time-based one time passwords, with sha1, 6 digits and 30 second periods,
+/// which is the only combination every authenticator app supports
+#[derive(Clone)]
+pub struct Totp {
+    secret: String,
+    key: Vec<u8>,
+}
+
+impl std::fmt::Debug for Totp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Totp").finish_non_exhaustive()
+    }
+}
+
+impl Totp {
+    /// a new random secret
+    #[must_use]
+    pub fn generate() -> Self {
+        let key = random_bytes(20);
+        Self {
+            secret: base32_encode(&key),
+            key,
+        }
+    }
+
+    /// `secret` is base32, like the ones returned by `secret`
+    pub fn from_secret(secret: &str) -> Option<Self> {
+        let key = base32_decode(secret).filter(|key| !key.is_empty())?;
+        Some(Self {
+            secret: base32_encode(&key),
+            key,
+        })
+    }
+
+    /// base32 secret, for storing, and for typing into apps that can't scan the qr code
+    pub fn secret(&self) -> &str {
+        &self.secret
+    }
+
+    /// the current time step
+    pub fn current_step() -> i64 {
+        Utc::now().timestamp() / PERIOD
+    }
+
+    pub fn code_at(&self, step: i64) -> String {
+        let mut mac =
+            Hmac::<Sha1>::new_from_slice(&self.key).expect("hmac accepts keys of any size");
+        mac.update(&step.to_be_bytes());
+        let hash = mac.finalize().into_bytes();
+
+        // dynamic truncation, from rfc 4226
+        let offset = (hash[hash.len() - 1] & 0xf) as usize;
+        let value = u32::from_be_bytes([
+            hash[offset] & 0x7f,
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]);
+        format!(
+            "{:0width$}",
+            value % 10u32.pow(DIGITS),
+            width = DIGITS as usize
+        )
+    }
+
+    /// the step `code` belongs to, if it's valid at `step` give or take the skew
+    ///
+    /// codes from `last_step` or earlier are rejected, so that each code can only be used once
+    pub fn verify_at(&self, code: &str, step: i64, last_step: Option<i64>) -> Option<i64> {
+        let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+        (step - SKEW..=step + SKEW)
+            .filter(|&s| last_step.is_none_or(|last| s > last))
+            .find(|&s| {
+                crate::helpers::constant_time_eq(self.code_at(s).as_bytes(), code.as_bytes())
+            })
+    }
+
+    /// `otpauth://` uri for authenticator apps, usually shown as a qr code
+    ///
+    /// `issuer` is the app's name, and `account` is the user's email or username
+    pub fn provisioning_uri(&self, issuer: &str, account: &str) -> String {
+        // spaces have to be `%20`, not `+`
+        let encode = |s: &str| {
+            form_urlencoded::byte_serialize(s.as_bytes())
+                .collect::<String>()
+                .replace('+', "%20")
+        };
+        format!(
+            "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={DIGITS}&period={PERIOD}",
+            encode(issuer),
+            encode(account),
+            self.secret,
+            encode(issuer),
+        )
+    }
+}
+
+/// `data` as a qr code, in an inline `svg`
+///
+/// ```ignore
+/// (qr_code(&setup.uri)?)
+/// ```
+#[cfg(feature = "qr_code")]
+pub fn qr_code(data: &str) -> Result<maud::Markup, ErrResponse> {
+    use qrcode::{render::svg, QrCode};
+
+    let svg = QrCode::new(data.as_bytes())?
+        .render::<svg::Color<'_>>()
+        .min_dimensions(200, 200)
+        .build();
+    // drop the `<?xml ...?>` declaration, it doesn't belong inside html
+    let start = svg.find("<svg").unwrap_or_default();
+    Ok(maud::PreEscaped(svg[start..].to_string()))
+}
+
+/// a secret that was just generated by `TwoFactor::enable`
+#[derive(Debug, Clone)]
+pub struct TwoFactorSetup {
+    pub secret: String,
+    /// `otpauth://` uri, to show as a qr code
+    pub uri: String,
+}
+
+/// totp two-factor authentication, and recovery codes for when the phone is lost
+///
+/// secrets and the last used code are stored in the `two_factor` table,
+/// and recovery codes hashed in `two_factor_recovery_codes` (see `muxa::db::migrate`).
+/// register it with `Auth::with_two_factor` so that `Auth::attempt` asks for a code
+/// from users that have it enabled
+#[derive(Clone)]
+pub struct TwoFactor {
+    pool: DbPool,
+    issuer: String,
+}
+
+#[derive(sqlx::FromRow)]
+struct Row {
+    secret: String,
+    confirmed_at: Option<DateTime<Utc>>,
+    last_step: Option<i64>,
+}
+
+/// recovery codes look like `abcd-efgh`
+fn recovery_code() -> String {
+    let code = base32_encode(&random_bytes(5)).to_ascii_lowercase();
+    format!("{}-{}", &code[..4], &code[4..])
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+impl TwoFactor {
+    /// `issuer` is the name authenticator apps show next to the codes
+    #[must_use]
+    pub fn new(pool: DbPool, issuer: impl ToString) -> Self {
+        Self {
+            pool,
+            issuer: issuer.to_string(),
+        }
+    }
+
+    async fn row(&self, user_id: &str) -> Result<Option<Row>, ErrResponse> {
+        let q = sql("SELECT secret, confirmed_at, last_step FROM two_factor WHERE user_id = ?");
+        Ok(sqlx::query_as(&q)
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?)
+    }
+
+    /// whether the user has to enter a code when logging in
+    pub async fn is_enabled(&self, user_id: &str) -> Result<bool, ErrResponse> {
+        Ok(self
+            .row(user_id)
+            .await?
+            .is_some_and(|row| row.confirmed_at.is_some()))
+    }
+
+    /// starts setting up two-factor, replacing any previous unconfirmed secret
+    /// it isn't enabled until the user proves they saved it, with `confirm`
+    ///
+    /// returns a 409 if it's already enabled, it has to be disabled first.
+    /// `account` is shown in the authenticator app, usually the user's email
+    pub async fn enable(
+        &self,
+        user_id: &str,
+        account: &str,
+    ) -> Result<TwoFactorSetup, ErrResponse> {
+        let totp = Totp::generate();
+
+        let mut tx = self.pool.begin().await?;
+        let q = sql("DELETE FROM two_factor WHERE user_id = ? AND confirmed_at IS NULL");
+        sqlx::query(&q).bind(user_id).execute(&mut *tx).await?;
+        // if it's confirmed, the row is still there and this fails
+        let result = sqlx::query(&sql(
+            "INSERT INTO two_factor (user_id, secret) VALUES (?, ?)",
+        ))
+        .bind(user_id)
+        .bind(totp.secret())
+        .execute(&mut *tx)
+        .await;
+        match result {
+            Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+                return Err(ErrResponse::new(
+                    StatusCode::CONFLICT,
+                    "two-factor is already enabled",
+                ));
+            }
+            result => result?,
+        };
+        tx.commit().await?;
+
+        Ok(TwoFactorSetup {
+            secret: totp.secret().to_string(),
+            uri: totp.provisioning_uri(&self.issuer, account),
+        })
+    }
+
+    /// enables two-factor if `code` is right, and returns the recovery codes,
+    /// which have to be shown to the user now, since only their hashes are stored
+    pub async fn confirm(
+        &self,
+        user_id: &str,
+        code: &str,
+    ) -> Result<Option<Vec<String>>, ErrResponse> {
+        let row = match self.row(user_id).await? {
+            Some(row) if row.confirmed_at.is_none() => row,
+            _ => return Ok(None),
+        };
+        let totp = Totp::from_secret(&row.secret).ok_or_else(invalid_secret)?;
+        let step = match totp.verify_at(code, Totp::current_step(), None) {
+            Some(step) => step,
+            None => return Ok(None),
+        };
+
+        let q = sql("UPDATE two_factor SET confirmed_at = ?, last_step = ? WHERE user_id = ?");
+        sqlx::query(&q)
+            .bind(Utc::now())
+            .bind(step)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(Some(self.regenerate_recovery_codes(user_id).await?))
+    }
+
+    /// counts an attempt at `verify`, returns false if there were too many
+    ///
+    /// it's a single statement, so that concurrent requests can't all get through
+    /// before any of them is counted
+    async fn start_attempt(&self, user_id: &str) -> Result<bool, ErrResponse> {
+        let now = Utc::now();
+        let window_start = now - Duration::minutes(ATTEMPTS_WINDOW_MINUTES);
+        let q = sql("UPDATE two_factor SET
+                attempts = CASE WHEN attempts_since IS NULL OR attempts_since < ?
+                    THEN 1 ELSE attempts + 1 END,
+                attempts_since = CASE WHEN attempts_since IS NULL OR attempts_since < ?
+                    THEN ? ELSE attempts_since END
+            WHERE user_id = ? AND (attempts_since IS NULL OR attempts_since < ? OR attempts < ?)");
+        let result = sqlx::query(&q)
+            .bind(window_start)
+            .bind(window_start)
+            .bind(now)
+            .bind(user_id)
+            .bind(window_start)
+            .bind(MAX_ATTEMPTS)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn reset_attempts(&self, user_id: &str) -> Result<(), ErrResponse> {
+        let q = sql("UPDATE two_factor SET attempts = 0, attempts_since = NULL WHERE user_id = ?");
+        sqlx::query(&q).bind(user_id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    /// checks a code from the authenticator app, or a recovery code
+    ///
+    /// each code only works once. recovery codes are deleted when used.
+    /// after `MAX_ATTEMPTS` wrong codes, this returns a 429 for the next 15 minutes
+    pub async fn verify(&self, user_id: &str, code: &str) -> Result<bool, ErrResponse> {
+        let row = match self.row(user_id).await? {
+            Some(row) if row.confirmed_at.is_some() => row,
+            _ => return Ok(false),
+        };
+        let totp = Totp::from_secret(&row.secret).ok_or_else(invalid_secret)?;
+
+        if !self.start_attempt(user_id).await? {
+            return Err(ErrResponse::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                "too many wrong codes, try again later",
+            ));
+        }
+
+        let valid = if let Some(step) = totp.verify_at(code, Totp::current_step(), row.last_step) {
+            // if two requests use the same code at once, only one of them updates it
+            let q = sql("UPDATE two_factor SET last_step = ?
+                WHERE user_id = ? AND (last_step IS NULL OR last_step < ?)");
+            let result = sqlx::query(&q)
+                .bind(step)
+                .bind(user_id)
+                .bind(step)
+                .execute(&self.pool)
+                .await?;
+            result.rows_affected() > 0
+        } else {
+            let q =
+                sql("DELETE FROM two_factor_recovery_codes WHERE user_id = ? AND code_hash = ?");
+            let result = sqlx::query(&q)
+                .bind(user_id)
+                .bind(hash_token(&normalize_recovery_code(code)))
+                .execute(&self.pool)
+                .await?;
+            result.rows_affected() > 0
+        };
+
+        if valid {
+            self.reset_attempts(user_id).await?;
+        }
+        Ok(valid)
+    }
+
+    /// replaces the user's recovery codes, and returns the new ones
+    pub async fn regenerate_recovery_codes(
+        &self,
+        user_id: &str,
+    ) -> Result<Vec<String>, ErrResponse> {
+        let codes: Vec<String> = (0..RECOVERY_CODES).map(|_| recovery_code()).collect();
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(&sql(
+            "DELETE FROM two_factor_recovery_codes WHERE user_id = ?",
+        ))
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        let q = sql("INSERT INTO two_factor_recovery_codes (user_id, code_hash) VALUES (?, ?)");
+        for code in &codes {
+            sqlx::query(&q)
+                .bind(user_id)
+                .bind(hash_token(&normalize_recovery_code(code)))
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(codes)
+    }
+
+    /// how many recovery codes the user has left
+    pub async fn recovery_codes_left(&self, user_id: &str) -> Result<i64, ErrResponse> {
+        let q = sql("SELECT COUNT(*) FROM two_factor_recovery_codes WHERE user_id = ?");
+        let (count,): (i64,) = sqlx::query_as(&q)
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count)
+    }
+
+    /// turns two-factor off, deleting the secret and recovery codes
+    pub async fn disable(&self, user_id: &str) -> Result<(), ErrResponse> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(&sql("DELETE FROM two_factor WHERE user_id = ?"))
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&sql(
+            "DELETE FROM two_factor_recovery_codes WHERE user_id = ?",
+        ))
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+#[track_caller]
+fn invalid_secret() -> ErrResponse {
+    ErrResponse::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "invalid two-factor secret",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_totp() {
+        // the sha1 test vectors from rfc 6238, truncated to 6 digits
+        let totp = Totp::from_secret(&base32_encode(b"12345678901234567890")).unwrap();
+        assert_eq!(totp.code_at(59 / PERIOD), "287082");
+        assert_eq!(totp.code_at(1111111109 / PERIOD), "081804");
+        assert_eq!(totp.code_at(2000000000 / PERIOD), "279037");
+
+        let step = 1111111109 / PERIOD;
+        assert_eq!(totp.verify_at("081 804", step, None), Some(step));
+        assert_eq!(totp.verify_at("081804", step + 1, None), Some(step));
+        assert_eq!(totp.verify_at("081804", step + 2, None), None);
+        // already used
+        assert_eq!(totp.verify_at("081804", step, Some(step)), None);
+
+        let secret = Totp::generate();
+        let same = Totp::from_secret(&secret.secret().to_lowercase()).unwrap();
+        assert_eq!(secret.code_at(5), same.code_at(5));
+        assert_eq!(
+            totp.provisioning_uri("My App", "annie@example.com"),
+            "otpauth://totp/My%20App:annie%40example.com?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+            &issuer=My%20App&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_enable_and_verify() {
+        let two_factor = TwoFactor::new(crate::db::test_pool().await, "muxa");
+        let setup = two_factor.enable("1", "annie").await.unwrap();
+        let totp = Totp::from_secret(&setup.secret).unwrap();
+        assert!(!two_factor.is_enabled("1").await.unwrap());
+
+        let step = Totp::current_step();
+        assert!(two_factor.confirm("1", "nope").await.unwrap().is_none());
+        let codes = two_factor
+            .confirm("1", &totp.code_at(step))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(two_factor.is_enabled("1").await.unwrap());
+
+        // codes can't be reused, or go backwards
+        assert!(!two_factor.verify("1", &totp.code_at(step)).await.unwrap());
+        assert!(two_factor
+            .verify("1", &totp.code_at(step + 1))
+            .await
+            .unwrap());
+        assert!(!two_factor
+            .verify("1", &totp.code_at(step + 1))
+            .await
+            .unwrap());
+
+        assert!(two_factor
+            .verify("1", &codes[0].to_uppercase())
+            .await
+            .unwrap());
+        assert!(!two_factor.verify("1", &codes[0]).await.unwrap());
+        assert_eq!(two_factor.recovery_codes_left("1").await.unwrap(), 7);
+
+        // enabling again would throw away the secret and leave the recovery codes
+        let err = two_factor.enable("1", "annie").await.unwrap_err();
+        assert_eq!(
+            axum::response::IntoResponse::into_response(err).status(),
+            StatusCode::CONFLICT
+        );
+
+        two_factor.disable("1").await.unwrap();
+        assert!(!two_factor.is_enabled("1").await.unwrap());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_too_many_attempts() {
+        let two_factor = TwoFactor::new(crate::db::test_pool().await, "muxa");
+        let setup = two_factor.enable("1", "annie").await.unwrap();
+        let totp = Totp::from_secret(&setup.secret).unwrap();
+        let step = Totp::current_step();
+        two_factor
+            .confirm("1", &totp.code_at(step))
+            .await
+            .unwrap()
+            .unwrap();
+
+        // a right code resets the count
+        for _ in 0..MAX_ATTEMPTS - 1 {
+            assert!(!two_factor.verify("1", "nope").await.unwrap());
+        }
+        assert!(two_factor
+            .verify("1", &totp.code_at(step + 1))
+            .await
+            .unwrap());
+
+        for _ in 0..MAX_ATTEMPTS {
+            assert!(!two_factor.verify("1", "nope").await.unwrap());
+        }
+        // even the right code is rejected now
+        assert!(two_factor
+            .verify("1", &totp.code_at(step + 2))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,307 @@
+use axum::http::StatusCode;
+use chrono::{Duration, Utc};
+use std::fmt::Display;
+
+use crate::{
+    config::Config,
+    db::{sql, DbPool},
+    errors::ErrResponse,
+    helpers::{constant_time_eq, hash_token, random_token},
+};
+
+/// name of the query param containing the token, see `TokenQuery`
+pub const TOKEN_PARAM: &str = "token";
+
+/// what a `UserTokens` token is for, tokens for one purpose can't be used for another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+}
+
+impl TokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::VerifyEmail => "verify_email",
+            Self::ResetPassword => "reset_password",
+        }
+    }
+}
+
+/// query of the links made by `UserTokens`
+///
+/// ```ignore
+/// async fn verify(Query(TokenQuery { token }): Query<TokenQuery>, tokens: Extension<UserTokens>) {
+///     match tokens.verify_email(&token).await? { ... }
+/// }
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct TokenQuery {
+    pub token: String,
+}
+
+/// single-use tokens for "click the link in your email" flows,
+/// like email verification and password resets
+///
+/// tokens are stored hashed in the `user_tokens` table (see `muxa::db::migrate`).
+/// making a new link replaces the user's previous one, and can only be done
+/// once every `throttle`, so that resend buttons can't be used to spam people
+#[derive(Clone)]
+pub struct UserTokens {
+    pool: DbPool,
+    config: Config,
+    throttle: Duration,
+    verify_email_lifetime: Duration,
+    reset_password_lifetime: Duration,
+}
+
+#[derive(sqlx::FromRow)]
+struct Row {
+    token_hash: String,
+    user_id: String,
+}
+
+impl UserTokens {
+    /// links can be resent every minute, verification links last a day,
+    /// and password reset links an hour
+    #[must_use]
+    pub fn new(pool: DbPool, config: Config) -> Self {
+        Self {
+            pool,
+            config,
+            throttle: Duration::minutes(1),
+            verify_email_lifetime: Duration::days(1),
+            reset_password_lifetime: Duration::hours(1),
+        }
+    }
+
+    /// how long users have to wait before getting another link
+    pub fn with_throttle(mut self, throttle: Duration) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    /// how long links for `purpose` last
+    pub fn with_lifetime(mut self, purpose: TokenPurpose, lifetime: Duration) -> Self {
+        match purpose {
+            TokenPurpose::VerifyEmail => self.verify_email_lifetime = lifetime,
+            TokenPurpose::ResetPassword => self.reset_password_lifetime = lifetime,
+        }
+        self
+    }
+
+    pub fn lifetime(&self, purpose: TokenPurpose) -> Duration {
+        match purpose {
+            TokenPurpose::VerifyEmail => self.verify_email_lifetime,
+            TokenPurpose::ResetPassword => self.reset_password_lifetime,
+        }
+    }
+
+    /// absolute url to `route`, usually a `NamedRoute`, with a new token for the user
+    /// returns a 429 if the user got a link for `purpose` less than `throttle` ago
+    pub async fn link(
+        &self,
+        user_id: &str,
+        purpose: TokenPurpose,
+        route: impl Display,
+    ) -> Result<String, ErrResponse> {
+        let token = self.create(user_id, purpose).await?;
+        let route = route.to_string();
+        let separator = if route.contains('?') { '&' } else { '?' };
+        Ok(self
+            .config
+            .absolute_url(&format!("{route}{separator}{TOKEN_PARAM}={token}")))
+    }
+
+    /// link for confirming the user's email, see `link`
+    pub async fn verification_link(
+        &self,
+        user_id: &str,
+        route: impl Display,
+    ) -> Result<String, ErrResponse> {
+        self.link(user_id, TokenPurpose::VerifyEmail, route).await
+    }
+
+    /// link for choosing a new password, see `link`
+    pub async fn password_reset_link(
+        &self,
+        user_id: &str,
+        route: impl Display,
+    ) -> Result<String, ErrResponse> {
+        self.link(user_id, TokenPurpose::ResetPassword, route).await
+    }
+
+    async fn create(&self, user_id: &str, purpose: TokenPurpose) -> Result<String, ErrResponse> {
+        let id = random_token(12);
+        let secret = random_token(32);
+        let now = Utc::now();
+
+        let mut tx = self.pool.begin().await?;
+
+        // only the newest link works, and it can only be replaced after `throttle`
+        let q =
+            sql("DELETE FROM user_tokens WHERE user_id = ? AND purpose = ? AND created_at <= ?");
+        sqlx::query(&q)
+            .bind(user_id)
+            .bind(purpose.as_str())
+            .bind(now - self.throttle)
+            .execute(&mut *tx)
+            .await?;
+
+        // there's a unique index on (user_id, purpose), so if a recent link is still there,
+        // or a concurrent request just made one, this fails
+        let q = sql(
+            "INSERT INTO user_tokens (id, token_hash, user_id, purpose, created_at, expires)
+            VALUES (?, ?, ?, ?, ?, ?)",
+        );
+        let result = sqlx::query(&q)
+            .bind(&id)
+            .bind(hash_token(&secret))
+            .bind(user_id)
+            .bind(purpose.as_str())
+            .bind(now)
+            .bind(now + self.lifetime(purpose))
+            .execute(&mut *tx)
+            .await;
+        match result {
+            Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+                return Err(ErrResponse::new(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    "a link was sent recently, try again later",
+                ));
+            }
+            result => result?,
+        };
+        tx.commit().await?;
+
+        Ok(format!("{id}.{secret}"))
+    }
+
+    /// uses up the token, and returns the id of the user it was made for
+    /// returns `None` if it's wrong, expired, already used, or for another purpose
+    pub async fn consume(
+        &self,
+        purpose: TokenPurpose,
+        token: &str,
+    ) -> Result<Option<String>, ErrResponse> {
+        let (id, secret) = match token.split_once('.') {
+            Some(parts) => parts,
+            None => return Ok(None),
+        };
+
+        let q = sql("SELECT token_hash, user_id FROM user_tokens
+            WHERE id = ? AND purpose = ? AND expires > ?");
+        let row: Option<Row> = sqlx::query_as(&q)
+            .bind(id)
+            .bind(purpose.as_str())
+            .bind(Utc::now())
+            .fetch_optional(&self.pool)
+            .await?;
+        let row = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        if !constant_time_eq(hash_token(secret).as_bytes(), row.token_hash.as_bytes()) {
+            return Ok(None);
+        }
+
+        // if two requests get here with the same token, only one deletes it
+        let result = sqlx::query(&sql("DELETE FROM user_tokens WHERE id = ?"))
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok((result.rows_affected() > 0).then_some(row.user_id))
+    }
+
+    /// consumes an email verification token, see `consume`
+    pub async fn verify_email(&self, token: &str) -> Result<Option<String>, ErrResponse> {
+        self.consume(TokenPurpose::VerifyEmail, token).await
+    }
+
+    /// consumes a password reset token, see `consume`
+    ///
+    /// after changing the password, the user's other sessions and remember-me tokens
+    /// should be revoked, see `DbSessionStore::revoke_all_sessions` and `RememberMe::forget_user`
+    pub async fn reset_password(&self, token: &str) -> Result<Option<String>, ErrResponse> {
+        self.consume(TokenPurpose::ResetPassword, token).await
+    }
+
+    /// deletes expired tokens, returns how many were deleted
+    pub async fn cleanup(&self) -> Result<u64, ErrResponse> {
+        let result = sqlx::query(&sql("DELETE FROM user_tokens WHERE expires < ?"))
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::{db::test_pool, tests::helpers::test_config};
+    use axum::response::IntoResponse;
+
+    async fn tokens() -> UserTokens {
+        UserTokens::new(test_pool().await, test_config())
+    }
+
+    fn token(link: &str) -> &str {
+        link.split_once("token=").unwrap().1
+    }
+
+    #[tokio::test]
+    async fn test_links_are_single_use() {
+        let tokens = tokens().await;
+        let link = tokens.verification_link("1", "/verify").await.unwrap();
+        assert!(link.starts_with("https://example.com/verify?token="));
+
+        let err = tokens
+            .verification_link("1", "/verify")
+            .await
+            .unwrap_err()
+            .into_response();
+        assert_eq!(err.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        assert_eq!(tokens.reset_password(token(&link)).await.unwrap(), None);
+        assert_eq!(
+            tokens.verify_email(token(&link)).await.unwrap().as_deref(),
+            Some("1")
+        );
+        assert_eq!(tokens.verify_email(token(&link)).await.unwrap(), None);
+
+        // other users and purposes aren't throttled
+        let link = tokens
+            .password_reset_link("2", "/reset?lang=en")
+            .await
+            .unwrap();
+        assert!(link.starts_with("https://example.com/reset?lang=en&token="));
+        assert!(tokens.reset_password("nope").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_expiry_and_resending() {
+        let tokens = tokens()
+            .await
+            .with_throttle(Duration::zero())
+            .with_lifetime(TokenPurpose::ResetPassword, Duration::seconds(-1));
+        let link = tokens.password_reset_link("1", "/reset").await.unwrap();
+        assert_eq!(tokens.reset_password(token(&link)).await.unwrap(), None);
+
+        // a new link replaces the old one
+        let first = tokens.verification_link("1", "/verify").await.unwrap();
+        let second = tokens.verification_link("1", "/verify").await.unwrap();
+        assert_eq!(tokens.verify_email(token(&first)).await.unwrap(), None);
+        assert!(tokens.verify_email(token(&second)).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_links_are_throttled() {
+        let tokens = tokens().await;
+        let (a, b) = tokio::join!(
+            tokens.verification_link("1", "/verify"),
+            tokens.verification_link("1", "/verify"),
+        );
+        assert!(a.is_ok() != b.is_ok());
+    }
+}
@@ -0,0 +1,379 @@
+//! protection against cross-site request forgery
+//!
+//! every session has a token (see `UserSession::csrf_token`), which forms send back
+//! in the `_token` field (see `html::components::csrf_field`), and javascript
+//! in the `X-CSRF-Token` header. `csrf_middleware` rejects unsafe requests without it
+//!
+//! multipart bodies aren't read by the middleware, so that uploads don't have to be buffered.
+//! their `_token` field is checked by `extractors::multipart::Multipart`, and has to be the
+//! first field of the form. requests whose token was never checked get a 403
+//!
+//! it isn't part of `default_layers!`, add it to the router before them:
+//!
+//! ```ignore
+//! Router::new()
+//!     .route(...)
+//!     .layer(axum::middleware::from_fn(muxa::csrf::csrf_middleware))
+//!     .layer(default_layers!(...))
+//! ```
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    headers::{Cookie, HeaderMapExt},
+    http::{
+        header::{CONTENT_LENGTH, CONTENT_TYPE},
+        request::Parts,
+        Method, Request, StatusCode,
+    },
+    middleware::Next,
+    response::Response,
+};
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::{
+    auth::bearer_token, errors::ErrResponse, helpers::constant_time_eq, sessions::UserSession,
+};
+
+/// name of the form field containing the token
+pub const CSRF_FIELD: &str = "_token";
+/// name of the header containing the token
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// largest urlencoded body that's read to find the token, same as axum's `DefaultBodyLimit`
+pub const MAX_FORM_SIZE: usize = 2 * 1024 * 1024;
+
+/// added to multipart requests without the token in the header or query,
+/// whose `_token` field is checked by `extractors::multipart::Multipart`
+#[derive(Clone)]
+pub(crate) struct PendingCsrfCheck {
+    expected: String,
+    checked: Arc<AtomicBool>,
+}
+
+impl PendingCsrfCheck {
+    pub(crate) fn check(&self, token: Option<&str>) -> Result<(), ErrResponse> {
+        check(&self.expected, token)?;
+        self.checked.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+/// `url` with the token in the query, for multipart forms whose handler
+/// doesn't use muxa's `Multipart`. prefer the `_token` field, since urls end up
+/// in the history, in logs, and in the `Referer` header
+pub fn url_with_token(url: &str, token: &str) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair(CSRF_FIELD, token)
+        .finish();
+    format!("{url}{separator}{query}")
+}
+
+#[track_caller]
+fn check(expected: &str, token: Option<&str>) -> Result<(), ErrResponse> {
+    match token {
+        Some(token) if constant_time_eq(expected.as_bytes(), token.as_bytes()) => Ok(()),
+        _ => Err(ErrResponse::new(
+            StatusCode::FORBIDDEN,
+            "invalid csrf token",
+        )),
+    }
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    )
+}
+
+fn query_token(parts: &Parts) -> Option<String> {
+    form_urlencoded::parse(parts.uri.query()?.as_bytes())
+        .find(|(k, _)| k == CSRF_FIELD)
+        .map(|(_, v)| v.into_owned())
+}
+
+/// reads the whole body, or returns a 413 if it's longer than `MAX_FORM_SIZE`
+async fn read_form(parts: &Parts, mut body: Body) -> Result<Bytes, ErrResponse> {
+    let too_large = || ErrResponse::new(StatusCode::PAYLOAD_TOO_LARGE, "form is too large");
+
+    let length = parts
+        .headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse::<usize>().ok());
+    if length.is_some_and(|length| length > MAX_FORM_SIZE) {
+        return Err(too_large());
+    }
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > MAX_FORM_SIZE {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes.into())
+}
+
+/// checks the csrf token of POST, PUT, PATCH and DELETE requests,
+/// must be added after `session_middleware`, see the module docs
+///
+/// the token is read from the `X-CSRF-Token` header, or the `_token` field of urlencoded forms.
+/// requests with an `Authorization: Bearer` header are let through if they don't have
+/// a session, see `auth::BearerToken`.
+/// multipart forms are checked by muxa's `Multipart` extractor, see the module docs,
+/// or can send the header or a `_token` query param
+pub async fn csrf_middleware(
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Result<Response, ErrResponse> {
+    if is_safe(req.method()) {
+        return Ok(next.run(req).await);
+    }
+
+    let session = req
+        .extensions()
+        .get::<UserSession>()
+        .expect("`session_middleware` missing");
+    let expected = session.csrf_token();
+    let cookie_name = session.cookie_name();
+    // eg logged back in by a `RememberMe` cookie
+    let logged_in = session.user_id().is_some();
+
+    let (parts, body) = req.into_parts();
+    // browsers can't add this header to cross-site requests, and api tokens aren't cookies.
+    // but with a session cookie, the handler might still use the session
+    let has_session_cookie = parts
+        .headers
+        .typed_get::<Cookie>()
+        .is_some_and(|cookies| cookies.get(&cookie_name).is_some());
+    if bearer_token(&parts).is_some() && !has_session_cookie && !logged_in {
+        return Ok(next.run(Request::from_parts(parts, body)).await);
+    }
+
+    let content_type = parts
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+
+    let (token, body) = if let Some(token) = parts.headers.get(CSRF_HEADER) {
+        (token.to_str().ok().map(ToString::to_string), body)
+    } else if content_type.starts_with("multipart/form-data") {
+        match query_token(&parts) {
+            Some(token) => (Some(token), body),
+            None => return check_multipart(expected, parts, body, next).await,
+        }
+    } else if content_type.starts_with("application/x-www-form-urlencoded") {
+        // the body has to be read to get the token, and then put back for the handler
+        let bytes = read_form(&parts, body).await?;
+        let token = form_urlencoded::parse(&bytes)
+            .find(|(k, _)| k == CSRF_FIELD)
+            .map(|(_, v)| v.into_owned());
+        (token, Body::from(bytes))
+    } else {
+        (None, body)
+    };
+
+    check(&expected, token.as_deref())?;
+    Ok(next.run(Request::from_parts(parts, body)).await)
+}
+
+/// leaves the check to `Multipart`, and rejects the request if it never happened,
+/// eg because the handler used axum's extractor, or didn't read the body
+async fn check_multipart(
+    expected: String,
+    mut parts: Parts,
+    body: Body,
+    next: Next<Body>,
+) -> Result<Response, ErrResponse> {
+    let pending = PendingCsrfCheck {
+        expected,
+        checked: Arc::new(AtomicBool::new(false)),
+    };
+    parts.extensions.insert(pending.clone());
+
+    let res = next.run(Request::from_parts(parts, body)).await;
+    if !pending.checked.load(Ordering::SeqCst) {
+        tracing::error!("csrf token of multipart request wasn't checked, use muxa's `Multipart`");
+        return Err(ErrResponse::new(
+            StatusCode::FORBIDDEN,
+            "invalid csrf token",
+        ));
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extractors::multipart::Multipart;
+    use crate::{
+        sessions::{session_middleware, MemoryStore},
+        tests::helpers::*,
+    };
+    use axum::{
+        extract::Extension,
+        http::{header::COOKIE, HeaderValue},
+        routing::get,
+        Form, Router,
+    };
+    use std::collections::HashMap;
+
+    #[derive(serde::Deserialize)]
+    struct Upload {
+        name: String,
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/token",
+                get(|session: Extension<UserSession>| async move { session.csrf_token() }),
+            )
+            .route(
+                "/",
+                axum::routing::post(|Form(form): Form<HashMap<String, String>>| async move {
+                    form.get("name").cloned().unwrap_or_default()
+                }),
+            )
+            .route("/upload", axum::routing::post(|| async { "uploaded" }))
+            .route(
+                "/multipart",
+                axum::routing::post(|Multipart(form): Multipart<Upload>| async move { form.name }),
+            )
+            .layer(axum::middleware::from_fn(csrf_middleware))
+            .layer(axum::middleware::from_fn(
+                session_middleware::<MemoryStore, _>,
+            ))
+            .layer(Extension(MemoryStore::new()))
+            .layer(Extension(test_config()))
+    }
+
+    fn form(cookie: &str, body: String) -> Request<Body> {
+        let mut req = post("/", Body::from(body));
+        req.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+        req.headers_mut()
+            .insert(COOKIE, HeaderValue::from_str(cookie).unwrap());
+        req
+    }
+
+    #[tokio::test]
+    async fn test_form_and_header_tokens() {
+        let app = app();
+        let res = app.clone().req(empty_get("/token")).await;
+        let token = std::str::from_utf8(&res.bytes).unwrap().to_string();
+        let cookie = session_cookie(&res);
+
+        let res = app.clone().req(form(&cookie, "name=annie".into())).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let res = app
+            .clone()
+            .req(form(&cookie, "name=annie&_token=wrong".into()))
+            .await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // the handler still gets the whole body
+        let res = app
+            .clone()
+            .req(form(&cookie, format!("name=annie&_token={token}")))
+            .await;
+        assert!(res.contains_str("annie"));
+
+        let mut req = form(&cookie, "name=annie".into());
+        req.headers_mut()
+            .insert(CSRF_HEADER, HeaderValue::from_str(&token).unwrap());
+        assert!(app.clone().req(req).await.contains_str("annie"));
+
+        // a bearer token only skips the check when there's no session cookie
+        let bearer = |mut req: Request<Body>| {
+            req.headers_mut().insert(
+                axum::http::header::AUTHORIZATION,
+                HeaderValue::from_static("Bearer anything"),
+            );
+            req
+        };
+        let res = app
+            .clone()
+            .req(bearer(form(&cookie, "name=annie".into())))
+            .await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let mut req = bearer(form(&cookie, "name=annie".into()));
+        req.headers_mut().remove(COOKIE);
+        assert!(app.clone().req(req).await.contains_str("annie"));
+    }
+
+    #[tokio::test]
+    async fn test_multipart_and_large_forms() {
+        let app = app();
+        let res = app.clone().req(empty_get("/token")).await;
+        let token = std::str::from_utf8(&res.bytes).unwrap().to_string();
+        let cookie = session_cookie(&res);
+
+        let multipart = |uri: &str, fields: &[(&str, &str)]| {
+            let mut body = String::new();
+            for (name, value) in fields {
+                body += &format!(
+                    "--x\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+                );
+            }
+            body += "--x--\r\n";
+            let mut req = post(uri, Body::from(body));
+            req.headers_mut().insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("multipart/form-data; boundary=x"),
+            );
+            req.headers_mut()
+                .insert(COOKIE, HeaderValue::from_str(&cookie).unwrap());
+            req
+        };
+
+        // the token is checked by `Multipart`, and has to come first
+        let res = app
+            .clone()
+            .req(multipart(
+                "/multipart",
+                &[("_token", &token), ("name", "annie")],
+            ))
+            .await;
+        assert!(res.contains_str("annie"));
+        for fields in [
+            &[("name", "annie")][..],
+            &[("_token", "wrong"), ("name", "annie")],
+            &[("name", "annie"), ("_token", &token)],
+        ] {
+            let res = app.clone().req(multipart("/multipart", fields)).await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        }
+
+        // the handler never checks the token, so the middleware rejects it
+        let res = app
+            .clone()
+            .req(multipart("/upload", &[("_token", &token)]))
+            .await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = app
+            .clone()
+            .req(multipart("/upload?_token=wrong", &[]))
+            .await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = app
+            .clone()
+            .req(multipart(&url_with_token("/upload", &token), &[]))
+            .await;
+        assert!(res.contains_str("uploaded"));
+
+        let body = format!("_token={token}&name={}", "a".repeat(MAX_FORM_SIZE));
+        let res = app.clone().req(form(&cookie, body)).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
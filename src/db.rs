@@ -0,0 +1,165 @@
+use sqlx::Executor;
+use std::borrow::Cow;
+
+use crate::errors::ErrResponse;
+
+#[cfg(feature = "mysql")]
+pub type DbPool = sqlx::MySqlPool;
+#[cfg(feature = "sqlite")]
+pub type DbPool = sqlx::SqlitePool;
+#[cfg(feature = "postgres")]
+pub type DbPool = sqlx::PgPool;
+
+/// queries are written with `?` placeholders, which postgres doesn't understand,
+/// so this turns them into `$1`, `$2`...
+///
+/// the query must not contain `?` anywhere else, eg inside string literals
+pub(crate) fn sql(query: &str) -> Cow<'_, str> {
+    if cfg!(feature = "postgres") {
+        let mut out = String::with_capacity(query.len() + 8);
+        let mut n = 0;
+        for c in query.chars() {
+            if c == '?' {
+                n += 1;
+                out.push_str(&format!("${n}"));
+            } else {
+                out.push(c);
+            }
+        }
+        Cow::Owned(out)
+    } else {
+        Cow::Borrowed(query)
+    }
+}
+
+#[cfg(feature = "mysql")]
+macro_rules! migrations_dir {
+    () => {
+        "mysql"
+    };
+}
+#[cfg(feature = "sqlite")]
+macro_rules! migrations_dir {
+    () => {
+        "sqlite"
+    };
+}
+#[cfg(feature = "postgres")]
+macro_rules! migrations_dir {
+    () => {
+        "postgres"
+    };
+}
+
+macro_rules! migrations {
+    ( $( $version:literal => $name:literal ),* $(,)? ) => {
+        &[
+            $(
+                Migration {
+                    version: $version,
+                    description: $name,
+                    sql: include_str!(concat!(
+                        "../migrations/",
+                        migrations_dir!(),
+                        "/",
+                        $name,
+                        ".sql"
+                    )),
+                },
+            )*
+        ]
+    };
+}
+
+/// a migration for one of muxa's own tables
+#[derive(Debug)]
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+/// muxa's migrations, for the database selected by the `mysql`/`sqlite`/`postgres` feature
+pub const MIGRATIONS: &[Migration] = migrations![
+    1 => "0001_create_sessions",
+    2 => "0002_add_session_owners",
+    3 => "0003_add_session_version",
+    4 => "0004_create_remember_tokens",
+    5 => "0005_create_api_tokens",
+    6 => "0006_create_user_tokens",
+    7 => "0007_create_two_factor",
+];
+
+/// applied migrations are tracked here, instead of in `_sqlx_migrations`,
+/// so that they don't interfere with the app's own `sqlx::migrate!`
+const MIGRATIONS_TABLE: &str = "CREATE TABLE IF NOT EXISTS _muxa_migrations (
+    version BIGINT NOT NULL PRIMARY KEY,
+    description VARCHAR(255) NOT NULL,
+    installed_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+)";
+
+/// creates and updates the tables muxa uses, like `sessions`
+///
+/// migrations that were already applied are skipped, so it's safe to call on every startup
+pub async fn migrate(pool: &DbPool) -> Result<(), ErrResponse> {
+    pool.execute(MIGRATIONS_TABLE).await?;
+
+    let applied: Vec<(i64,)> = sqlx::query_as("SELECT version FROM _muxa_migrations")
+        .fetch_all(pool)
+        .await?;
+
+    for migration in MIGRATIONS {
+        if applied.iter().any(|(v,)| *v == migration.version) {
+            continue;
+        }
+
+        tracing::info!(
+            version = migration.version,
+            "applying muxa migration {}",
+            migration.description
+        );
+
+        let q = sql("INSERT INTO _muxa_migrations (version, description) VALUES (?, ?)");
+
+        let mut tx = pool.begin().await?;
+        (&mut *tx).execute(migration.sql).await?;
+        sqlx::query(&q)
+            .bind(migration.version)
+            .bind(migration.description)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
+
+/// in-memory database with muxa's migrations applied
+#[cfg(all(test, feature = "sqlite"))]
+pub(crate) async fn test_pool() -> DbPool {
+    // every connection to `:memory:` gets a different database
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    migrate(&pool).await.unwrap();
+    pool
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_migrate_is_idempotent() {
+        let pool = test_pool().await;
+        migrate(&pool).await.unwrap();
+
+        let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM _muxa_migrations")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, MIGRATIONS.len() as i64);
+    }
+}
@@ -12,7 +12,12 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tokio::{fs::File, io::BufWriter};
 use tokio_util::io::StreamReader;
 
-use crate::{config::Config, errors::*, helpers::struct_fields};
+use crate::{
+    config::Config,
+    csrf::{PendingCsrfCheck, CSRF_FIELD},
+    errors::*,
+    helpers::struct_fields,
+};
 
 /// uploaded file struct in multipart
 #[derive(Debug, Serialize, Deserialize)]
@@ -40,9 +45,22 @@ where
             .get::<Config>()
             .expect("Config Extension should be added")
             .clone();
+        // added by `csrf_middleware` when the token wasn't in the header or query
+        let csrf = req.extensions().get::<PendingCsrfCheck>().cloned();
 
         let mut f = axum::extract::multipart::Multipart::from_request(req, state).await?;
 
+        if let Some(csrf) = csrf {
+            // `_token` has to be the first field, so that nothing is read,
+            // and no files are written, before it's checked
+            match f.next_field().await? {
+                Some(field) if field.name() == Some(CSRF_FIELD) => {
+                    csrf.check(Some(&field.text().await?))?
+                }
+                _ => csrf.check(None)?,
+            }
+        }
+
         #[derive(Debug, Serialize)]
         #[serde(untagged)]
         enum FieldInner {
@@ -69,6 +87,11 @@ where
                 (name, false)
             };
 
+            // the token was in the header or query, or already checked above
+            if name == CSRF_FIELD {
+                continue;
+            }
+
             if !allowed_fields.contains(&name.as_str()) {
                 continue;
             }
@@ -94,6 +94,37 @@ pub fn truncate_to_length(s: &str, len: usize) -> Cow<'_, str> {
     }
 }
 
+/// `len` random bytes from the OS, for secrets that aren't sent as text, see `random_token`
+pub fn random_bytes(len: usize) -> Vec<u8> {
+    use rand::RngCore;
+
+    let mut buf = vec![0u8; len];
+    rand::rngs::OsRng.fill_bytes(&mut buf);
+    buf
+}
+
+/// `bytes` random bytes from the OS, encoded as url-safe base64
+/// used for tokens that end up in cookies, forms and links
+pub fn random_token(bytes: usize) -> String {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+    URL_SAFE_NO_PAD.encode(random_bytes(bytes))
+}
+
+/// sha256 of a random token, for storing it in the database
+/// only for tokens made with `random_token`, passwords need `auth::hash_password`
+pub fn hash_token(token: &str) -> String {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use sha2::{Digest, Sha256};
+
+    URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+/// compares two secrets without leaking how much of them matched through timing
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
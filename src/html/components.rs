@@ -24,3 +24,20 @@ pub fn debug(v: impl Debug) -> Markup {
         (format!("{v:?}"))
     }
 }
+
+/// hidden input with the csrf token, goes inside every form that isn't a GET
+/// in multipart forms, it has to be the first field, see `muxa::csrf`
+///
+/// usually used through `HtmlContext::csrf_field`
+pub fn csrf_field(token: &str) -> Markup {
+    html! {
+        input type="hidden" name=(crate::csrf::CSRF_FIELD) value=(token);
+    }
+}
+
+/// `meta name="csrf-token"`, for javascript to send the token in the `X-CSRF-Token` header
+pub fn csrf_meta(token: &str) -> Markup {
+    html! {
+        meta name="csrf-token" content=(token);
+    }
+}
@@ -1,4 +1,9 @@
-use crate::{config::Config, errors::*, sessions::UserSession};
+use crate::{
+    auth::Abilities,
+    config::Config,
+    errors::*,
+    sessions::{FlashMessage, UserSession},
+};
 use axum::{
     extract::{FromRequestParts, Query},
     http::Request,
@@ -16,7 +21,8 @@ pub mod components;
 #[derive(Clone)]
 pub struct HtmlContextBuilder<T, R> {
     query: HashMap<String, String>,
-    pub session_flash: Option<String>,
+    pub session_flash: Vec<FlashMessage>,
+    session: UserSession,
     config: Config,
     route: R,
     inner: T,
@@ -47,7 +53,8 @@ where
         let Query(query) = Query::<HashMap<String, String>>::from_request_parts(&mut parts, &())
             .await
             .unwrap();
-        let session_flash = parts.extensions.get::<UserSession>().unwrap().get_flash();
+        let session = parts.extensions.get::<UserSession>().unwrap().clone();
+        let session_flash = session.get_flash();
         let config = parts.extensions.get::<Config>().unwrap().clone();
         let inner = T::from_request_parts(&mut parts, &())
             .await
@@ -61,6 +68,7 @@ where
         parts.extensions.insert(HtmlContextBuilder {
             query,
             session_flash,
+            session,
             config,
             route,
             inner,
@@ -80,6 +88,7 @@ impl<T, R> HtmlContextBuilder<T, R> {
             content,
             query: self.query,
             session_flash: self.session_flash,
+            session: self.session,
             config: self.config,
             route: self.route,
 
@@ -88,6 +97,7 @@ impl<T, R> HtmlContextBuilder<T, R> {
             image: None,
 
             sections: Default::default(),
+            abilities: Default::default(),
 
             inner: self.inner,
         }
@@ -98,7 +108,8 @@ impl<T, R> HtmlContextBuilder<T, R> {
 pub struct HtmlContext<T, R> {
     pub content: Markup,
     pub query: HashMap<String, String>,
-    pub session_flash: Option<String>,
+    pub session_flash: Vec<FlashMessage>,
+    pub session: UserSession,
     pub config: Config,
     pub route: R,
 
@@ -107,6 +118,7 @@ pub struct HtmlContext<T, R> {
     pub image: Option<String>,
 
     pub sections: HashMap<String, Vec<Markup>>,
+    pub abilities: Abilities,
 
     pub inner: T,
 }
@@ -167,6 +179,49 @@ impl<T, R> HtmlContext<T, R> {
         self
     }
 
+    /// flash messages with the given key
+    pub fn flash_for<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a FlashMessage> {
+        self.session_flash
+            .iter()
+            .filter(move |m| m.key.as_deref() == Some(key))
+    }
+
+    /// the session's csrf token, see `muxa::csrf`
+    pub fn csrf_token(&self) -> String {
+        self.session.csrf_token()
+    }
+
+    /// hidden input with the csrf token, for forms
+    pub fn csrf_field(&self) -> Markup {
+        components::csrf_field(&self.csrf_token())
+    }
+
+    /// `url` with the csrf token in the query, for the action of multipart forms
+    pub fn csrf_url(&self, url: &str) -> String {
+        crate::csrf::url_with_token(url, &self.csrf_token())
+    }
+
+    /// actions checked with `Auth::abilities`, for `can` and `can_on`
+    pub fn with_abilities(mut self, abilities: Abilities) -> Self {
+        self.abilities = abilities;
+        self
+    }
+
+    /// whether the user can do `action`, for showing or hiding controls
+    /// false for actions that weren't passed to `with_abilities`
+    ///
+    /// ```ignore
+    /// @if ctx.can("posts.create") { a href=(route_new_post()) { "new post" } }
+    /// ```
+    pub fn can(&self, action: &str) -> bool {
+        self.abilities.can(action, None)
+    }
+
+    /// same as `can`, for an action on a resource
+    pub fn can_on(&self, action: &str, resource: &str) -> bool {
+        self.abilities.can(action, Some(resource))
+    }
+
     pub fn section_get(&self, key: &str) -> Markup {
         let section: &[Markup] = self
             .sections
@@ -3,15 +3,19 @@
 #[macro_use]
 extern crate serde;
 
+pub mod auth;
 pub mod config;
 pub mod consts;
 pub mod cookies;
+pub mod csrf;
+pub mod db;
 pub mod errors;
 pub mod extractors;
 pub mod helpers;
 pub mod html;
 pub mod router;
 pub mod sessions;
+pub mod signed_urls;
 pub mod tests;
 pub mod theme;
 pub mod tracing;
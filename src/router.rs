@@ -51,6 +51,8 @@ impl RouterExtension for Router {
 }
 
 /// adds the layers required by muxa, plus app-related extensions
+///
+/// csrf protection is opt-in, see `muxa::csrf`
 #[macro_export]
 macro_rules! default_layers {
     (
@@ -69,7 +71,9 @@ macro_rules! default_layers {
             .layer(axum::extract::Extension(
                 muxa::sessions::DbSessionStore::new($pool).with_same_site(muxa::cookies::SameSite::Lax),
             ))
-            .layer(axum::middleware::from_fn(muxa::sessions::session_middleware))
+            .layer(axum::middleware::from_fn(
+              muxa::sessions::session_middleware::<muxa::sessions::DbSessionStore, _>,
+            ))
             .layer(axum::middleware::from_fn(
               <$builder as muxa::html::AssociatedMiddleware<_>>::Middleware::html_context_middleware,
             ))
@@ -0,0 +1,379 @@
+use async_session::Session;
+use axum::async_trait;
+use lru::LruCache;
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use super::{
+    info::SessionInfo,
+    store::{id_from_cookie_value, SessionStore},
+    DbSessionStore, SessionChanges, SessionConfig, UserSession,
+};
+use crate::errors::ErrResponse;
+
+/// keeps recently loaded sessions in memory, in front of another store,
+/// usually `DbSessionStore`
+///
+/// sessions stored, destroyed or revoked through this store are removed from the cache
+/// right away, but changes made from other processes, or by calling `DbSessionStore`
+/// directly, are only seen once the cached session is older than `ttl`
+///
+/// ```ignore
+/// let store = CachedSessionStore::new(DbSessionStore::new(pool))
+///     .with_capacity(10_000)
+///     .with_ttl(Duration::from_secs(30));
+/// ```
+#[derive(Clone)]
+pub struct CachedSessionStore<S> {
+    inner: S,
+    cache: Arc<Mutex<Cache>>,
+    ttl: Duration,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+enum Entry {
+    Session {
+        cached_at: Instant,
+        /// serialized, so that two requests never share the same session data
+        session: String,
+    },
+    /// the session was stored or destroyed at `ticket`, so loads that started
+    /// before that have an old copy, which mustn't be cached
+    Invalidated { ticket: u64 },
+}
+
+struct Cache {
+    entries: LruCache<String, Entry>,
+    /// incremented when a load starts and when a session is invalidated,
+    /// to tell which happened first
+    ticket: u64,
+    /// highest ticket of an `Invalidated` entry that was evicted,
+    /// loads that started before it can't be cached, since we don't know which session it was
+    evicted: u64,
+}
+
+impl Cache {
+    fn next_ticket(&mut self) -> u64 {
+        self.ticket += 1;
+        self.ticket
+    }
+
+    fn push(&mut self, id: String, entry: Entry) {
+        if let Some((evicted_id, Entry::Invalidated { ticket })) =
+            self.entries.push(id.clone(), entry)
+        {
+            if evicted_id != id {
+                self.evicted = self.evicted.max(ticket);
+            }
+        }
+    }
+}
+
+impl<S: SessionStore> CachedSessionStore<S> {
+    /// caches up to 10000 sessions, for 30 seconds
+    #[must_use]
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            cache: Arc::new(Mutex::new(Cache {
+                entries: LruCache::new(NonZeroUsize::new(10_000).unwrap()),
+                ticket: 0,
+                evicted: 0,
+            })),
+            ttl: Duration::from_secs(30),
+            hits: Default::default(),
+            misses: Default::default(),
+        }
+    }
+
+    /// how many sessions are kept, the least recently used ones are dropped first
+    pub fn with_capacity(self, capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity.max(1)).unwrap();
+        self.cache.lock().unwrap().entries.resize(capacity);
+        self
+    }
+
+    /// how long a session is served from memory before it's loaded again
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// loads that were served from memory
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// loads that went to the inner store
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    fn get_cached(&self, id: &str) -> Option<String> {
+        let mut cache = self.cache.lock().unwrap();
+        match cache.entries.get(id) {
+            Some(Entry::Session { cached_at, session }) if cached_at.elapsed() < self.ttl => {
+                Some(session.clone())
+            }
+            Some(Entry::Session { .. }) => {
+                cache.entries.pop(id);
+                None
+            }
+            Some(Entry::Invalidated { .. }) | None => None,
+        }
+    }
+
+    /// has to be called before loading from the inner store, and passed to `fill`
+    fn start_load(&self) -> u64 {
+        self.cache.lock().unwrap().next_ticket()
+    }
+
+    /// caches a session loaded from the inner store,
+    /// unless it was stored or destroyed after the load started
+    fn fill(&self, id: String, ticket: u64, session: String) {
+        let mut cache = self.cache.lock().unwrap();
+        if ticket <= cache.evicted {
+            return;
+        }
+        if let Some(Entry::Invalidated {
+            ticket: invalidated,
+        }) = cache.entries.peek(&id)
+        {
+            if *invalidated > ticket {
+                return;
+            }
+        }
+        cache.push(
+            id,
+            Entry::Session {
+                cached_at: Instant::now(),
+                session,
+            },
+        );
+    }
+
+    fn invalidate(&self, id: &str) {
+        let mut cache = self.cache.lock().unwrap();
+        let ticket = cache.next_ticket();
+        cache.push(id.to_string(), Entry::Invalidated { ticket });
+    }
+
+    /// drops every cached session, and keeps loads that already started from being cached
+    fn invalidate_all(&self) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.evicted = cache.next_ticket();
+        cache.entries.clear();
+    }
+}
+
+/// the same as `DbSessionStore`'s methods, but revoked sessions are removed from the cache
+impl CachedSessionStore<DbSessionStore> {
+    pub async fn sessions_for_user(&self, user_id: &str) -> Result<Vec<SessionInfo>, ErrResponse> {
+        self.inner.sessions_for_user(user_id).await
+    }
+
+    pub async fn revoke_session(
+        &self,
+        user_id: &str,
+        session_id: &str,
+    ) -> Result<bool, ErrResponse> {
+        let result = self.inner.revoke_session(user_id, session_id).await;
+        self.invalidate(session_id);
+        result
+    }
+
+    /// the whole cache is dropped, since we don't know which sessions were the user's
+    pub async fn revoke_other_sessions(
+        &self,
+        user_id: &str,
+        current: &UserSession,
+    ) -> Result<u64, ErrResponse> {
+        let result = self.inner.revoke_other_sessions(user_id, current).await;
+        self.invalidate_all();
+        result
+    }
+
+    /// the whole cache is dropped, like with `revoke_other_sessions`
+    pub async fn revoke_all_sessions(&self, user_id: &str) -> Result<u64, ErrResponse> {
+        let result = self.inner.revoke_all_sessions(user_id).await;
+        self.invalidate_all();
+        result
+    }
+}
+
+#[async_trait]
+impl<S: SessionStore> SessionStore for CachedSessionStore<S> {
+    async fn load_session(&self, cookie_value: String) -> Result<Option<Session>, ErrResponse> {
+        let id = id_from_cookie_value(&cookie_value)?;
+
+        if let Some(cached) = self.get_cached(&id) {
+            let hits = self.hits.fetch_add(1, Ordering::Relaxed) + 1;
+            tracing::trace!(hits, misses = self.misses(), "session cache hit");
+
+            let session: Session = serde_json::from_str(&cached)?;
+            return Ok(Some(session).filter(|s| !self.config().is_expired(s)));
+        }
+
+        let misses = self.misses.fetch_add(1, Ordering::Relaxed) + 1;
+        tracing::trace!(hits = self.hits(), misses, "session cache miss");
+
+        let ticket = self.start_load();
+        let session = self.inner.load_session(cookie_value).await?;
+        if let Some(session) = &session {
+            self.fill(id, ticket, serde_json::to_string(session)?);
+        }
+        Ok(session)
+    }
+
+    async fn store_session(&self, session: Session) -> Result<Option<String>, ErrResponse> {
+        let id = session.id().to_string();
+        let result = self.inner.store_session(session).await;
+        // a load that started before this point may have read the old version,
+        // `fill` checks the ticket so that it doesn't end up cached
+        self.invalidate(&id);
+        result
+    }
+
+    async fn store_changes(
+        &self,
+        session: Session,
+        changes: &SessionChanges,
+    ) -> Result<Option<String>, ErrResponse> {
+        let id = session.id().to_string();
+        let result = self.inner.store_changes(session, changes).await;
+        self.invalidate(&id);
+        result
+    }
+
+    async fn destroy_session(&self, session: Session) -> Result<(), ErrResponse> {
+        let id = session.id().to_string();
+        let result = self.inner.destroy_session(session).await;
+        self.invalidate(&id);
+        result
+    }
+
+    async fn cleanup(&self) -> Result<u64, ErrResponse> {
+        self.inner.cleanup().await
+    }
+
+    fn config(&self) -> &SessionConfig {
+        self.inner.config()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sessions::MemoryStore;
+
+    #[tokio::test]
+    async fn test_hits_and_invalidation() {
+        let store = CachedSessionStore::new(MemoryStore::new());
+        let mut session = Session::new();
+        session.insert("hey", "hello").unwrap();
+        let cookie = store.store_session(session).await.unwrap().unwrap();
+
+        let loaded = store.load_session(cookie.clone()).await.unwrap().unwrap();
+        store.load_session(cookie.clone()).await.unwrap().unwrap();
+        assert_eq!((store.hits(), store.misses()), (1, 1));
+
+        // stores go through to the inner store, and the next load misses
+        let mut changed = loaded.clone();
+        changed.insert("hey", "bye").unwrap();
+        store.store_session(changed).await.unwrap();
+        let loaded = store.load_session(cookie.clone()).await.unwrap().unwrap();
+        assert_eq!(loaded.get::<String>("hey"), Some("bye".to_string()));
+        assert_eq!((store.hits(), store.misses()), (1, 2));
+
+        store.destroy_session(loaded).await.unwrap();
+        assert!(store.load_session(cookie).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_loads_racing_with_invalidation() {
+        let store = CachedSessionStore::new(MemoryStore::new()).with_capacity(1);
+
+        // a load misses and reads the row, then the session is destroyed,
+        // then the load tries to cache what it read
+        let ticket = store.start_load();
+        store.invalidate("a");
+        store.fill("a".to_string(), ticket, "{}".to_string());
+        assert!(store.get_cached("a").is_none());
+
+        // loads that start after the invalidation can be cached
+        let ticket = store.start_load();
+        store.fill("a".to_string(), ticket, "{}".to_string());
+        assert!(store.get_cached("a").is_some());
+
+        // the tombstone is evicted, so the old load can't be cached either
+        let ticket = store.start_load();
+        store.invalidate("b");
+        store.invalidate("c");
+        store.fill("b".to_string(), ticket, "{}".to_string());
+        assert!(store.get_cached("b").is_none());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_revoking_invalidates() {
+        use crate::sessions::info::USER_ID_KEY;
+
+        let store = CachedSessionStore::new(DbSessionStore::new(crate::db::test_pool().await));
+        let shared: Arc<dyn SessionStore> = Arc::new(store.clone());
+
+        let mut cookies = vec![];
+        let mut sessions = vec![];
+        for _ in 0..3 {
+            let mut session = Session::new();
+            session.insert(USER_ID_KEY, "1").unwrap();
+            let cookie = store.store_session(session).await.unwrap().unwrap();
+            // cache it
+            let loaded = store.load_session(cookie.clone()).await.unwrap().unwrap();
+            sessions.push((
+                loaded.id().to_string(),
+                UserSession::new(loaded, shared.clone()),
+            ));
+            cookies.push(cookie);
+        }
+
+        assert!(store.revoke_session("1", &sessions[0].0).await.unwrap());
+        assert!(store
+            .load_session(cookies[0].clone())
+            .await
+            .unwrap()
+            .is_none());
+
+        store
+            .revoke_other_sessions("1", &sessions[1].1)
+            .await
+            .unwrap();
+        assert!(store
+            .load_session(cookies[2].clone())
+            .await
+            .unwrap()
+            .is_none());
+        assert!(store
+            .load_session(cookies[1].clone())
+            .await
+            .unwrap()
+            .is_some());
+
+        store.revoke_all_sessions("1").await.unwrap();
+        assert!(store
+            .load_session(cookies[1].clone())
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,139 @@
+use async_session::Session;
+use std::collections::HashMap;
+
+/// keys a request set or removed, compared to the session it loaded
+///
+/// stores use it to apply the request on top of the latest stored session,
+/// so that parallel requests on the same session don't overwrite each other
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionChanges {
+    /// serialized values, like `Session::get_raw`
+    set: HashMap<String, String>,
+    removed: Vec<String>,
+    /// what each changed key was when the session was loaded, `None` if it wasn't there
+    original: HashMap<String, Option<String>>,
+    loaded: bool,
+}
+
+/// the session's data, keys and serialized values
+pub(crate) fn session_data(session: &Session) -> HashMap<String, String> {
+    serde_json::to_value(session)
+        .ok()
+        .and_then(|mut v| serde_json::from_value(v.get_mut("data")?.take()).ok())
+        .unwrap_or_default()
+}
+
+impl SessionChanges {
+    /// what changed from `original` to `session`
+    /// `original` is `None` for sessions that weren't loaded from the store, or got a new id
+    pub(crate) fn between(original: Option<&HashMap<String, String>>, session: &Session) -> Self {
+        let loaded = original.is_some();
+        let original = original.cloned().unwrap_or_default();
+        let current = session_data(session);
+        let set: HashMap<_, _> = current
+            .iter()
+            .filter(|(k, v)| original.get(*k) != Some(*v))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        let removed: Vec<_> = original
+            .keys()
+            .filter(|k| !current.contains_key(*k))
+            .cloned()
+            .collect();
+        let original = set
+            .keys()
+            .chain(&removed)
+            .map(|k| (k.clone(), original.get(k).cloned()))
+            .collect();
+        Self {
+            set,
+            removed,
+            original,
+            loaded,
+        }
+    }
+
+    /// true if the session was in the store when the request started
+    ///
+    /// if it's not there anymore, another request destroyed it, so it shouldn't be stored again
+    pub fn was_loaded(&self) -> bool {
+        self.loaded
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty() && self.removed.is_empty()
+    }
+
+    /// applies the changes to `session`, usually the latest stored version
+    ///
+    /// keys that another request changed since this one loaded them are left alone,
+    /// so that eg a flash message stored by one request isn't removed by another one,
+    /// which only saw the previous message
+    pub fn apply_to(&self, session: &mut Session) {
+        let unchanged = |session: &Session, k: &str| {
+            session.get_raw(k) == self.original.get(k).cloned().flatten()
+        };
+        for (k, v) in &self.set {
+            if unchanged(session, k) {
+                session.insert_raw(k, v.clone());
+            }
+        }
+        for k in &self.removed {
+            if unchanged(session, k) {
+                session.remove(k);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disjoint_changes_are_merged() {
+        let mut stored = Session::new();
+        stored.insert("a", 1).unwrap();
+        stored.insert("b", 1).unwrap();
+        let original = session_data(&stored);
+
+        // two requests load the same session
+        let mut first: Session =
+            serde_json::from_value(serde_json::to_value(&stored).unwrap()).unwrap();
+        let mut second: Session =
+            serde_json::from_value(serde_json::to_value(&stored).unwrap()).unwrap();
+        first.insert("a", 2).unwrap();
+        first.insert("c", 1).unwrap();
+        second.remove("b");
+
+        let first = SessionChanges::between(Some(&original), &first);
+        let second = SessionChanges::between(Some(&original), &second);
+        first.apply_to(&mut stored);
+        second.apply_to(&mut stored);
+
+        assert_eq!(stored.get::<i32>("a"), Some(2));
+        assert_eq!(stored.get::<i32>("b"), None);
+        assert_eq!(stored.get::<i32>("c"), Some(1));
+    }
+
+    #[test]
+    fn test_keys_changed_by_others_are_kept() {
+        let mut stored = Session::new();
+        stored.insert("flash", "old").unwrap();
+        let original = session_data(&stored);
+
+        // an xhr loads the old flash, and removes it once it's been seen
+        let mut xhr: Session =
+            serde_json::from_value(serde_json::to_value(&stored).unwrap()).unwrap();
+        xhr.remove("flash");
+        xhr.insert("seen", true).unwrap();
+        let xhr = SessionChanges::between(Some(&original), &xhr);
+
+        // meanwhile, a form post stores a new flash
+        stored.insert("flash", "saved").unwrap();
+
+        xhr.apply_to(&mut stored);
+        assert_eq!(stored.get::<String>("flash").as_deref(), Some("saved"));
+        assert_eq!(stored.get::<bool>("seen"), Some(true));
+    }
+}
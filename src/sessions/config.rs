@@ -0,0 +1,175 @@
+use async_session::Session;
+use chrono::{DateTime, Duration, Utc};
+
+use super::{
+    expiry::{mark_stored, ExpiryPolicy},
+    SESSION_COOKIE_NAME,
+};
+use crate::cookies::SameSite;
+
+/// cookie attributes and expiry used by a `SessionStore`
+///
+/// the same values are used for the cookie and for the stored session,
+/// so they always expire at the same time
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    pub name: String,
+    pub domain: Option<String>,
+    pub path: String,
+    pub http_only: bool,
+    /// can be disabled for local development over http
+    pub secure: bool,
+    pub same_site: SameSite,
+    /// how long a session lives after it was last stored
+    pub lifetime: Duration,
+    /// idle timeout and absolute lifetime, can be overridden per session
+    pub expiry: ExpiryPolicy,
+    /// unchanged sessions are stored again after this much time, to push back their expiry.
+    /// a day by default, or a quarter of the idle timeout if that's shorter.
+    /// `None` only stores sessions when they change
+    pub touch_after: Option<Duration>,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            name: SESSION_COOKIE_NAME.to_string(),
+            domain: None,
+            path: "/".to_string(),
+            http_only: true,
+            secure: true,
+            same_site: SameSite::Strict,
+            lifetime: Duration::days(180),
+            expiry: ExpiryPolicy::default(),
+            touch_after: Some(Duration::days(1)),
+        }
+    }
+}
+
+impl SessionConfig {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_name(mut self, name: impl ToString) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn with_domain(mut self, domain: impl ToString) -> Self {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    pub fn with_path(mut self, path: impl ToString) -> Self {
+        self.path = path.to_string();
+        self
+    }
+
+    pub fn with_http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn with_secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn with_same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    pub fn with_lifetime(mut self, lifetime: Duration) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+
+    pub fn with_expiry(mut self, expiry: ExpiryPolicy) -> Self {
+        self.expiry = expiry;
+        self
+    }
+
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.expiry.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    pub fn with_absolute_lifetime(mut self, absolute_lifetime: Duration) -> Self {
+        self.expiry.absolute_lifetime = Some(absolute_lifetime);
+        self
+    }
+
+    pub fn with_touch_after(mut self, touch_after: Duration) -> Self {
+        self.touch_after = Some(touch_after);
+        self
+    }
+
+    /// only store sessions when they change, unless there's an idle timeout
+    pub fn without_touch_after(mut self) -> Self {
+        self.touch_after = None;
+        self
+    }
+
+    /// the session's own policy, or the default one
+    pub fn policy_for(&self, session: &Session) -> ExpiryPolicy {
+        ExpiryPolicy::from_session(session).unwrap_or(self.expiry)
+    }
+
+    /// when `session` will expire, if it's stored right now
+    pub fn expiry_for(&self, session: &Session) -> DateTime<Utc> {
+        self.policy_for(session).expiry(session, self.lifetime)
+    }
+
+    /// returns true if the idle timeout or the absolute lifetime have passed
+    pub fn is_expired(&self, session: &Session) -> bool {
+        session.is_expired() || self.policy_for(session).is_expired(session)
+    }
+
+    /// how long to wait before storing an unchanged session again
+    ///
+    /// never longer than a quarter of the idle timeout, so active sessions don't time out
+    pub fn touch_after_for(&self, session: &Session) -> Option<Duration> {
+        let idle = self
+            .policy_for(session)
+            .idle_timeout
+            .map(|idle_timeout| idle_timeout / 4);
+        match (self.touch_after, idle) {
+            (Some(touch_after), Some(idle)) => Some(touch_after.min(idle)),
+            (touch_after, idle) => touch_after.or(idle),
+        }
+    }
+
+    /// updates the session's timestamps and expiry before it gets stored,
+    /// and returns when it will expire
+    pub(crate) fn prepare_for_store(
+        &self,
+        session: &mut Session,
+    ) -> Result<DateTime<Utc>, serde_json::Error> {
+        let expires = self.expiry_for(session);
+        mark_stored(session)?;
+        session.set_expiry(expires);
+        Ok(expires)
+    }
+
+    /// value for the `Set-Cookie` header
+    /// `max_age` is in seconds
+    pub fn cookie(&self, value: &str, max_age: i64) -> String {
+        let mut cookie = format!(
+            "{}={}; SameSite={}; Path={}; Max-Age={};",
+            self.name, value, self.same_site, self.path, max_age
+        );
+        if let Some(domain) = &self.domain {
+            cookie.push_str(&format!(" Domain={domain};"));
+        }
+        if self.secure {
+            cookie.push_str(" Secure;");
+        }
+        if self.http_only {
+            cookie.push_str(" HttpOnly;");
+        }
+        cookie
+    }
+}
@@ -0,0 +1,203 @@
+use async_session::Session;
+use axum::{async_trait, http::StatusCode};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+
+use super::{store::SessionStore, SessionConfig};
+use crate::{cookies::SameSite, errors::ErrResponse};
+
+/// browsers only accept cookies up to this size, including the name and attributes
+pub const MAX_COOKIE_SIZE: usize = 4096;
+
+const NONCE_SIZE: usize = 12;
+
+/// stores the whole session inside the cookie, encrypted and authenticated
+/// with a key derived from an app secret
+///
+/// there's no server side state, so sessions can't be revoked before they expire,
+/// and `destroy` only expires the cookie
+///
+/// to rotate the secret, create the store with the new secret and pass the previous one
+/// to `with_old_secret`, so that existing cookies can still be decrypted
+#[derive(Clone)]
+pub struct CookieStore {
+    /// the first one is used to encrypt, all of them are tried when decrypting
+    keys: Vec<ChaCha20Poly1305>,
+    config: SessionConfig,
+}
+
+fn derive_key(secret: &[u8]) -> ChaCha20Poly1305 {
+    let key = Sha256::new()
+        .chain_update(b"muxa-cookie-store")
+        .chain_update(secret)
+        .finalize();
+    ChaCha20Poly1305::new(Key::from_slice(&key))
+}
+
+impl CookieStore {
+    /// `secret` should be long and random, eg 64 bytes read from an env variable
+    #[must_use]
+    pub fn new(secret: impl AsRef<[u8]>) -> Self {
+        Self {
+            keys: vec![derive_key(secret.as_ref())],
+            config: SessionConfig::default(),
+        }
+    }
+
+    /// a previous secret, only used to decrypt existing cookies
+    pub fn with_old_secret(mut self, secret: impl AsRef<[u8]>) -> Self {
+        self.keys.push(derive_key(secret.as_ref()));
+        self
+    }
+
+    pub fn with_config(mut self, config: SessionConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// shorthand for setting `SessionConfig::same_site`
+    pub fn with_same_site(mut self, same_site: SameSite) -> Self {
+        self.config.same_site = same_site;
+        self
+    }
+
+    fn decrypt(&self, cookie_value: &str) -> Option<Vec<u8>> {
+        let bytes = URL_SAFE_NO_PAD.decode(cookie_value).ok()?;
+        if bytes.len() < NONCE_SIZE {
+            return None;
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_SIZE);
+
+        self.keys.iter().find_map(|key| {
+            key.decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    // binds the value to the cookie name
+                    aad: self.config.name.as_bytes(),
+                },
+            )
+            .ok()
+        })
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Result<String, ErrResponse> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self.keys[0]
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: self.config.name.as_bytes(),
+                },
+            )
+            .map_err(|_| {
+                ErrResponse::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "couldn't encrypt session",
+                )
+            })?;
+
+        let mut bytes = nonce.to_vec();
+        bytes.extend(ciphertext);
+        Ok(URL_SAFE_NO_PAD.encode(bytes))
+    }
+}
+
+#[async_trait]
+impl SessionStore for CookieStore {
+    async fn load_session(&self, cookie_value: String) -> Result<Option<Session>, ErrResponse> {
+        let plaintext = match self.decrypt(&cookie_value) {
+            Some(plaintext) => plaintext,
+            None => {
+                tracing::debug!("couldn't decrypt session cookie");
+                return Ok(None);
+            }
+        };
+
+        let session: Session = serde_json::from_slice(&plaintext)?;
+        Ok(Some(session).filter(|s| !self.config.is_expired(s)))
+    }
+
+    async fn store_session(&self, mut session: Session) -> Result<Option<String>, ErrResponse> {
+        let expires = self.config.prepare_for_store(&mut session)?;
+        let value = self.encrypt(&serde_json::to_vec(&session)?)?;
+
+        let max_age = (expires - Utc::now()).num_seconds();
+        let cookie = self.config.cookie(&value, max_age);
+        if cookie.len() > MAX_COOKIE_SIZE {
+            return Err(ErrResponse::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!(
+                    "session is too big to be stored in a cookie: {} bytes, the limit is {MAX_COOKIE_SIZE}",
+                    cookie.len()
+                ),
+            ));
+        }
+
+        Ok(Some(value))
+    }
+
+    async fn destroy_session(&self, _session: Session) -> Result<(), ErrResponse> {
+        // nothing is stored server side, `session_middleware` expires the cookie
+        Ok(())
+    }
+
+    async fn cleanup(&self) -> Result<u64, ErrResponse> {
+        Ok(0)
+    }
+
+    fn config(&self) -> &SessionConfig {
+        &self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_round_trip_and_rotation() {
+        let mut session = Session::new();
+        session.insert("hey", "hello").unwrap();
+
+        let old = CookieStore::new("old secret");
+        let cookie = old.store_session(session).await.unwrap().unwrap();
+
+        let loaded = old.load_session(cookie.clone()).await.unwrap().unwrap();
+        assert_eq!(loaded.get::<String>("hey"), Some("hello".to_string()));
+
+        let new = CookieStore::new("new secret");
+        assert!(new.load_session(cookie.clone()).await.unwrap().is_none());
+
+        let rotated = CookieStore::new("new secret").with_old_secret("old secret");
+        assert!(rotated.load_session(cookie).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_tampered_cookie() {
+        let store = CookieStore::new("secret");
+        let cookie = store.store_session(Session::new()).await.unwrap().unwrap();
+
+        let mut bytes = URL_SAFE_NO_PAD.decode(&cookie).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let tampered = URL_SAFE_NO_PAD.encode(bytes);
+
+        assert!(store.load_session(tampered).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_too_big() {
+        let store = CookieStore::new("secret");
+        let mut session = Session::new();
+        session.insert("big", "a".repeat(MAX_COOKIE_SIZE)).unwrap();
+
+        assert!(store.store_session(session).await.is_err());
+    }
+}
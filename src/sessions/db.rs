@@ -0,0 +1,455 @@
+use async_session::Session;
+use axum::{async_trait, http::StatusCode};
+use chrono::{DateTime, Utc};
+
+use super::{
+    expiry,
+    info::{ClientInfo, SessionInfo, USER_ID_KEY},
+    store::{id_from_cookie_value, SessionStore},
+    SessionChanges, SessionConfig, UserSession, REMEMBER_SELECTOR_KEY,
+};
+use crate::{
+    cookies::SameSite,
+    db::{sql, DbPool},
+    errors::{internal_error, ErrResponse},
+};
+
+/// how many times `store_changes` retries when another request stored the session first
+const MAX_STORE_ATTEMPTS: usize = 5;
+
+/// stores sessions in the `sessions` table, see `muxa::db::migrate`
+///
+/// every row has a version, which is checked when storing the changes of a request,
+/// so parallel requests on one session don't overwrite each other
+///
+/// sessions with a user id (see `UserSession::set_user_id`) can be listed and revoked
+#[derive(Clone)]
+pub struct DbSessionStore {
+    pool: DbPool,
+    config: SessionConfig,
+}
+
+#[derive(sqlx::FromRow)]
+#[allow(dead_code)]
+struct InternalSession {
+    id: String,
+    // `TIMESTAMPTZ` on postgres, which can't be decoded into a `NaiveDateTime`
+    expires: DateTime<Utc>,
+    session: String,
+}
+
+impl DbSessionStore {
+    #[must_use]
+    pub fn new(pool: DbPool) -> Self {
+        Self {
+            pool,
+            config: SessionConfig::default(),
+        }
+    }
+
+    pub fn with_config(mut self, config: SessionConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// shorthand for setting `SessionConfig::same_site`
+    pub fn with_same_site(mut self, same_site: SameSite) -> Self {
+        self.config.same_site = same_site;
+        self
+    }
+
+    /// deletes at most `batch_size` expired sessions, and returns how many were deleted
+    ///
+    /// used by `SessionReaper` so that big tables don't get locked for too long
+    pub async fn cleanup_batch(&self, batch_size: u32) -> Result<u64, ErrResponse> {
+        #[cfg(feature = "mysql")]
+        let q = "DELETE FROM sessions WHERE expires < ? LIMIT ?";
+        #[cfg(not(feature = "mysql"))]
+        let q = "DELETE FROM sessions WHERE id IN
+                (SELECT id FROM sessions WHERE expires < ? LIMIT ?)";
+
+        let result = sqlx::query(&sql(q))
+            .bind(Utc::now())
+            .bind(i64::from(batch_size))
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// active sessions of a user, the most recently used first
+    pub async fn sessions_for_user(&self, user_id: &str) -> Result<Vec<SessionInfo>, ErrResponse> {
+        let q = sql(
+            "SELECT id, created_at, last_seen, user_agent, ip, expires FROM sessions
+            WHERE user_id = ? AND expires > ?
+            ORDER BY last_seen DESC",
+        );
+
+        let sessions = sqlx::query_as(&q)
+            .bind(user_id)
+            .bind(Utc::now())
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(sessions)
+    }
+
+    /// deletes one of the user's sessions, returns false if it doesn't exist
+    /// or belongs to someone else
+    ///
+    /// the `RememberMe` token it was issued is deleted too, so it can't log back in
+    pub async fn revoke_session(
+        &self,
+        user_id: &str,
+        session_id: &str,
+    ) -> Result<bool, ErrResponse> {
+        let q = sql("SELECT session FROM sessions WHERE user_id = ? AND id = ?");
+        let row: Option<(String,)> = sqlx::query_as(&q)
+            .bind(user_id)
+            .bind(session_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        let session: Session = match row {
+            Some((session,)) => serde_json::from_str(&session)?,
+            None => return Ok(false),
+        };
+
+        let mut tx = self.pool.begin().await?;
+        if let Some(selector) = session.get::<String>(REMEMBER_SELECTOR_KEY) {
+            let q = sql("DELETE FROM remember_tokens WHERE user_id = ? AND selector = ?");
+            sqlx::query(&q)
+                .bind(user_id)
+                .bind(selector)
+                .execute(&mut *tx)
+                .await?;
+        }
+        let q = sql("DELETE FROM sessions WHERE user_id = ? AND id = ?");
+        let result = sqlx::query(&q)
+            .bind(user_id)
+            .bind(session_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// deletes all of the user's sessions and `RememberMe` tokens, except `current` and its token
+    /// usually called after changing the password
+    pub async fn revoke_other_sessions(
+        &self,
+        user_id: &str,
+        current: &UserSession,
+    ) -> Result<u64, ErrResponse> {
+        let mut tx = self.pool.begin().await?;
+        match current.remember_selector() {
+            Some(selector) => {
+                let q = sql("DELETE FROM remember_tokens WHERE user_id = ? AND selector != ?");
+                sqlx::query(&q)
+                    .bind(user_id)
+                    .bind(selector)
+                    .execute(&mut *tx)
+                    .await?
+            }
+            None => {
+                let q = sql("DELETE FROM remember_tokens WHERE user_id = ?");
+                sqlx::query(&q).bind(user_id).execute(&mut *tx).await?
+            }
+        };
+        let q = sql("DELETE FROM sessions WHERE user_id = ? AND id != ?");
+        let result = sqlx::query(&q)
+            .bind(user_id)
+            .bind(current.id())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+
+    /// deletes all of the user's sessions and `RememberMe` tokens
+    pub async fn revoke_all_sessions(&self, user_id: &str) -> Result<u64, ErrResponse> {
+        let mut tx = self.pool.begin().await?;
+        let q = sql("DELETE FROM remember_tokens WHERE user_id = ?");
+        sqlx::query(&q).bind(user_id).execute(&mut *tx).await?;
+        let q = sql("DELETE FROM sessions WHERE user_id = ?");
+        let result = sqlx::query(&q).bind(user_id).execute(&mut *tx).await?;
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl SessionStore for DbSessionStore {
+    async fn load_session(&self, cookie_value: String) -> Result<Option<Session>, ErrResponse> {
+        let id = id_from_cookie_value(&cookie_value)?;
+        let q = sql("SELECT * FROM sessions
+        WHERE id = ? AND (expires IS NULL OR expires > ?)");
+
+        let result: Option<InternalSession> = sqlx::query_as(&q)
+            .bind(id)
+            .bind(Utc::now())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        let session: Option<Session> = result
+            .map(|r| serde_json::from_str(&r.session))
+            .transpose()
+            .map_err(internal_error)?;
+        Ok(session.filter(|s| !self.config.is_expired(s)))
+    }
+
+    async fn store_session(&self, mut session: Session) -> Result<Option<String>, ErrResponse> {
+        let expires = self.config.prepare_for_store(&mut session)?;
+        let session_string = serde_json::to_string(&session)?;
+
+        let user_id: Option<String> = session.get(USER_ID_KEY);
+        let client = ClientInfo::from_session(&session);
+
+        #[cfg(feature = "mysql")]
+        let q = "INSERT INTO sessions
+                (id, session, expires, user_id, created_at, last_seen, user_agent, ip)
+                VALUES(?, ?, ?, ?, ?, ?, ?, ?)
+            ON DUPLICATE KEY UPDATE
+                version = version + 1,
+                expires = VALUES(expires),
+                session = VALUES(session),
+                user_id = VALUES(user_id),
+                last_seen = VALUES(last_seen),
+                user_agent = VALUES(user_agent),
+                ip = VALUES(ip)";
+        #[cfg(not(feature = "mysql"))]
+        let q = "INSERT INTO sessions
+                (id, session, expires, user_id, created_at, last_seen, user_agent, ip)
+                VALUES(?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(id) DO UPDATE SET
+                version = sessions.version + 1,
+                session = excluded.session,
+                expires = excluded.expires,
+                user_id = excluded.user_id,
+                last_seen = excluded.last_seen,
+                user_agent = excluded.user_agent,
+                ip = excluded.ip";
+
+        sqlx::query(&sql(q))
+            .bind(session.id().to_string())
+            .bind(&session_string)
+            .bind(expires)
+            .bind(user_id)
+            .bind(expiry::created_at(&session))
+            .bind(expiry::last_seen(&session))
+            .bind(client.user_agent())
+            .bind(client.ip())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(session.into_cookie_value())
+    }
+
+    async fn store_changes(
+        &self,
+        session: Session,
+        changes: &SessionChanges,
+    ) -> Result<Option<String>, ErrResponse> {
+        for _ in 0..MAX_STORE_ATTEMPTS {
+            let q = sql("SELECT session, version FROM sessions WHERE id = ? AND expires > ?");
+            let row: Option<(String, i64)> = sqlx::query_as(&q)
+                .bind(session.id().to_string())
+                .bind(Utc::now())
+                .fetch_optional(&self.pool)
+                .await?;
+
+            let (stored, version) = match row {
+                Some(row) => row,
+                None if changes.was_loaded() => return Ok(None),
+                // new or regenerated session, there's nothing to merge with
+                None => return self.store_session(session).await,
+            };
+
+            let mut latest: Session = serde_json::from_str(&stored)?;
+            changes.apply_to(&mut latest);
+
+            let expires = self.config.prepare_for_store(&mut latest)?;
+            let user_id: Option<String> = latest.get(USER_ID_KEY);
+            let client = ClientInfo::from_session(&latest);
+
+            let q = sql("UPDATE sessions SET
+                    session = ?, expires = ?, user_id = ?, last_seen = ?, user_agent = ?, ip = ?,
+                    version = version + 1
+                WHERE id = ? AND version = ?");
+            let result = sqlx::query(&q)
+                .bind(serde_json::to_string(&latest)?)
+                .bind(expires)
+                .bind(user_id)
+                .bind(expiry::last_seen(&latest))
+                .bind(client.user_agent())
+                .bind(client.ip())
+                .bind(session.id().to_string())
+                .bind(version)
+                .execute(&self.pool)
+                .await?;
+
+            if result.rows_affected() > 0 {
+                return Ok(session.into_cookie_value());
+            }
+            tracing::debug!("session was stored by another request, merging again");
+        }
+
+        Err(ErrResponse::new(
+            StatusCode::CONFLICT,
+            "session was changed by too many requests at once",
+        ))
+    }
+
+    async fn destroy_session(&self, session: Session) -> Result<(), ErrResponse> {
+        sqlx::query(&sql("DELETE FROM sessions WHERE id = ?"))
+            .bind(session.id().to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn cleanup(&self) -> Result<u64, ErrResponse> {
+        let q = sql("DELETE FROM sessions WHERE expires < ?");
+
+        let result = sqlx::query(&q).bind(Utc::now()).execute(&self.pool).await?;
+        Ok(result.rows_affected())
+    }
+
+    fn config(&self) -> &SessionConfig {
+        &self.config
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::{
+        db::test_pool,
+        sessions::{
+            changes::session_data,
+            remember::{selector, RememberMe, Remembered},
+        },
+    };
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_store_and_cleanup() {
+        let store = DbSessionStore::new(test_pool().await);
+        let mut session = Session::new();
+        session.insert("hey", "hello").unwrap();
+
+        let cookie = store.store_session(session).await.unwrap().unwrap();
+        let loaded = store.load_session(cookie).await.unwrap().unwrap();
+        assert_eq!(loaded.get::<String>("hey"), Some("hello".to_string()));
+
+        let expired = DbSessionStore::new(store.pool.clone())
+            .with_config(SessionConfig::default().with_lifetime(chrono::Duration::days(-1)));
+        for _ in 0..3 {
+            expired.store_session(Session::new()).await.unwrap();
+        }
+        assert_eq!(store.cleanup_batch(2).await.unwrap(), 2);
+        assert_eq!(store.cleanup().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_parallel_changes_are_merged() {
+        let store = DbSessionStore::new(test_pool().await);
+        let mut session = Session::new();
+        session.insert("a", 1).unwrap();
+        let cookie = store.store_session(session).await.unwrap().unwrap();
+
+        // two requests load the session at the same time
+        let mut first = store.load_session(cookie.clone()).await.unwrap().unwrap();
+        let mut second = store.load_session(cookie.clone()).await.unwrap().unwrap();
+        let original = session_data(&first);
+        first.insert("b", 1).unwrap();
+        second.insert("c", 1).unwrap();
+
+        for session in [first, second] {
+            let changes = SessionChanges::between(Some(&original), &session);
+            store.store_changes(session, &changes).await.unwrap();
+        }
+
+        let loaded = store.load_session(cookie.clone()).await.unwrap().unwrap();
+        assert_eq!(loaded.get::<i32>("a"), Some(1));
+        assert_eq!(loaded.get::<i32>("b"), Some(1));
+        assert_eq!(loaded.get::<i32>("c"), Some(1));
+
+        // a destroyed session isn't brought back by a request that was still running
+        let changes = SessionChanges::between(Some(&original), &loaded);
+        store.destroy_session(loaded.clone()).await.unwrap();
+        assert!(store
+            .store_changes(loaded, &changes)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(store.load_session(cookie).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sessions_for_user() {
+        let db = DbSessionStore::new(test_pool().await);
+        let store: Arc<dyn SessionStore> = Arc::new(db.clone());
+
+        let mut sessions = vec![];
+        for _ in 0..3 {
+            let mut session = UserSession::new(Session::new(), store.clone());
+            session.set_user_id("1").await.unwrap();
+            session.save().await.unwrap();
+            sessions.push(session);
+        }
+        let mut other = UserSession::new(Session::new(), store);
+        other.set_user_id("2").await.unwrap();
+        other.save().await.unwrap();
+
+        assert_eq!(db.sessions_for_user("1").await.unwrap().len(), 3);
+        assert!(!db.revoke_session("2", &sessions[0].id()).await.unwrap());
+        assert!(db.revoke_session("1", &sessions[0].id()).await.unwrap());
+        assert_eq!(
+            db.revoke_other_sessions("1", &sessions[1]).await.unwrap(),
+            1
+        );
+
+        let left = db.sessions_for_user("1").await.unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].id, sessions[1].id());
+        assert_eq!(db.sessions_for_user("2").await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_revoking_forgets_remember_tokens() {
+        let db = DbSessionStore::new(test_pool().await);
+        let store: Arc<dyn SessionStore> = Arc::new(db.clone());
+        let remember = RememberMe::new(db.pool.clone());
+
+        let mut sessions = vec![];
+        let mut cookies = vec![];
+        for _ in 0..3 {
+            let cookie = remember.issue("1").await.unwrap();
+            let mut session = UserSession::new(Session::new(), store.clone());
+            session.set_user_id("1").await.unwrap();
+            session
+                .insert(REMEMBER_SELECTOR_KEY, selector(&cookie))
+                .await
+                .unwrap();
+            session.save().await.unwrap();
+            sessions.push(session);
+            cookies.push(cookie);
+        }
+        let remembered = |cookie: String| {
+            let remember = remember.clone();
+            async move {
+                matches!(
+                    remember.consume(&cookie).await.unwrap(),
+                    Remembered::User { .. }
+                )
+            }
+        };
+
+        assert!(db.revoke_session("1", &sessions[0].id()).await.unwrap());
+        assert!(!remembered(cookies[0].clone()).await);
+        db.revoke_other_sessions("1", &sessions[1]).await.unwrap();
+        assert!(!remembered(cookies[2].clone()).await);
+        assert!(remembered(cookies[1].clone()).await);
+
+        db.revoke_all_sessions("1").await.unwrap();
+        assert!(!remembered(cookies[1].clone()).await);
+    }
+}
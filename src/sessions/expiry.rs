@@ -0,0 +1,206 @@
+use async_session::Session;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+const CREATED_AT_KEY: &str = "internal-key-created-at";
+const LAST_SEEN_KEY: &str = "internal-key-last-seen";
+const EXPIRY_POLICY_KEY: &str = "internal-key-expiry-policy";
+
+/// when sessions expire, on top of `SessionConfig::lifetime`
+///
+/// set the default one with `SessionConfig::with_expiry`,
+/// and override it for a single session with `UserSession::set_expiry_policy`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExpiryPolicy {
+    /// sessions that aren't used for this long expire
+    ///
+    /// sessions are only stored when they change, or after `SessionConfig::touch_after`,
+    /// so a session might expire up to `touch_after` earlier than expected
+    pub idle_timeout: Option<Duration>,
+    /// sessions expire this long after they were created, no matter how much they are used
+    pub absolute_lifetime: Option<Duration>,
+}
+
+/// `ExpiryPolicy`, as it's stored inside the session
+#[derive(Serialize, Deserialize)]
+struct StoredPolicy {
+    idle_timeout: Option<i64>,
+    absolute_lifetime: Option<i64>,
+}
+
+impl ExpiryPolicy {
+    /// a policy that only uses `SessionConfig::lifetime`, eg for remember-me sessions
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    pub fn with_absolute_lifetime(mut self, absolute_lifetime: Duration) -> Self {
+        self.absolute_lifetime = Some(absolute_lifetime);
+        self
+    }
+
+    pub(crate) fn from_session(session: &Session) -> Option<Self> {
+        let stored: StoredPolicy = session.get(EXPIRY_POLICY_KEY)?;
+        Some(Self {
+            idle_timeout: stored.idle_timeout.map(Duration::seconds),
+            absolute_lifetime: stored.absolute_lifetime.map(Duration::seconds),
+        })
+    }
+
+    pub(crate) fn insert_into(&self, session: &mut Session) -> Result<(), serde_json::Error> {
+        session.insert(
+            EXPIRY_POLICY_KEY,
+            StoredPolicy {
+                idle_timeout: self.idle_timeout.map(|d| d.num_seconds()),
+                absolute_lifetime: self.absolute_lifetime.map(|d| d.num_seconds()),
+            },
+        )
+    }
+
+    /// when a session that is stored right now will expire
+    pub(crate) fn expiry(&self, session: &Session, lifetime: Duration) -> DateTime<Utc> {
+        let now = Utc::now();
+        let mut expires = now + lifetime;
+        if let Some(idle_timeout) = self.idle_timeout {
+            expires = expires.min(now + idle_timeout);
+        }
+        if let Some(absolute_lifetime) = self.absolute_lifetime {
+            let created_at = created_at(session).unwrap_or(now);
+            expires = expires.min(created_at + absolute_lifetime);
+        }
+        expires
+    }
+
+    pub(crate) fn is_expired(&self, session: &Session) -> bool {
+        let now = Utc::now();
+        let idle = matches!(
+            (self.idle_timeout, last_seen(session)),
+            (Some(idle_timeout), Some(last_seen)) if last_seen + idle_timeout <= now
+        );
+        let absolute = matches!(
+            (self.absolute_lifetime, created_at(session)),
+            (Some(absolute_lifetime), Some(created_at)) if created_at + absolute_lifetime <= now
+        );
+        idle || absolute
+    }
+}
+
+fn timestamp(session: &Session, key: &str) -> Option<DateTime<Utc>> {
+    Utc.timestamp_opt(session.get(key)?, 0).single()
+}
+
+/// when the session was first stored
+pub(crate) fn created_at(session: &Session) -> Option<DateTime<Utc>> {
+    timestamp(session, CREATED_AT_KEY)
+}
+
+/// when the session was last stored
+pub(crate) fn last_seen(session: &Session) -> Option<DateTime<Utc>> {
+    timestamp(session, LAST_SEEN_KEY)
+}
+
+/// makes the absolute lifetime start over the next time the session is stored,
+/// eg when logging in, instead of counting from the anonymous session
+pub(crate) fn reset_created_at(session: &mut Session) {
+    session.remove(CREATED_AT_KEY);
+}
+
+/// records the timestamps used by `ExpiryPolicy`, should be called right before storing the session
+pub(crate) fn mark_stored(session: &mut Session) -> Result<(), serde_json::Error> {
+    let now = Utc::now().timestamp();
+    if session.get::<i64>(CREATED_AT_KEY).is_none() {
+        session.insert(CREATED_AT_KEY, now)?;
+    }
+    session.insert(LAST_SEEN_KEY, now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sessions::{MemoryStore, SessionConfig, SessionStore};
+
+    #[tokio::test]
+    async fn test_policies_are_enforced_on_load() {
+        let config = SessionConfig::default()
+            .with_absolute_lifetime(Duration::hours(12))
+            .with_idle_timeout(Duration::minutes(30));
+        let store = MemoryStore::new().with_config(config);
+
+        let mut session = Session::new();
+        session
+            .insert(
+                CREATED_AT_KEY,
+                (Utc::now() - Duration::hours(13)).timestamp(),
+            )
+            .unwrap();
+        let cookie = store.store_session(session).await.unwrap().unwrap();
+        assert!(store.load_session(cookie).await.unwrap().is_none());
+
+        // a remember-me session only uses the lifetime
+        let mut session = Session::new();
+        session
+            .insert(
+                CREATED_AT_KEY,
+                (Utc::now() - Duration::hours(13)).timestamp(),
+            )
+            .unwrap();
+        ExpiryPolicy::none().insert_into(&mut session).unwrap();
+        let cookie = store.store_session(session).await.unwrap().unwrap();
+        assert!(store.load_session(cookie).await.unwrap().is_some());
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        let policy = ExpiryPolicy::none().with_idle_timeout(Duration::minutes(30));
+        let mut session = Session::new();
+        mark_stored(&mut session).unwrap();
+        assert!(!policy.is_expired(&session));
+
+        session
+            .insert(
+                LAST_SEEN_KEY,
+                (Utc::now() - Duration::minutes(31)).timestamp(),
+            )
+            .unwrap();
+        assert!(policy.is_expired(&session));
+    }
+
+    #[tokio::test]
+    async fn test_regenerate_resets_created_at() {
+        let mut session = Session::new();
+        session
+            .insert(
+                CREATED_AT_KEY,
+                (Utc::now() - Duration::hours(11)).timestamp(),
+            )
+            .unwrap();
+        let mut user_session =
+            crate::sessions::UserSession::new(session, std::sync::Arc::new(MemoryStore::new()));
+        user_session.regenerate().await.unwrap();
+
+        let mut session = user_session.session().clone();
+        assert!(created_at(&session).is_none());
+        mark_stored(&mut session).unwrap();
+        assert!(created_at(&session).unwrap() > Utc::now() - Duration::minutes(1));
+    }
+
+    #[test]
+    fn test_touch_after() {
+        let session = Session::new();
+        let config = SessionConfig::default();
+        assert_eq!(config.touch_after_for(&session), Some(Duration::days(1)));
+
+        let config = config.with_idle_timeout(Duration::hours(2));
+        assert_eq!(
+            config.touch_after_for(&session),
+            Some(Duration::minutes(30))
+        );
+
+        let config = SessionConfig::default().without_touch_after();
+        assert_eq!(config.touch_after_for(&session), None);
+    }
+}
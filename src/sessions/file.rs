@@ -0,0 +1,276 @@
+use async_session::Session;
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use std::{io::ErrorKind, path::PathBuf, sync::Arc};
+use tokio::sync::Mutex;
+
+use super::{
+    changes::SessionChanges,
+    store::{id_from_cookie_value, SessionStore},
+    SessionConfig,
+};
+use crate::{cookies::SameSite, errors::ErrResponse};
+
+/// stores every session as a json file inside a folder
+///
+/// writes are serialized within the process, so it shouldn't be shared between several servers
+#[derive(Clone)]
+pub struct FileStore {
+    path: PathBuf,
+    config: SessionConfig,
+    /// held from reading a session to writing it back, and while deleting
+    lock: Arc<Mutex<()>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileSession {
+    expires: DateTime<Utc>,
+    session: Session,
+}
+
+impl FileStore {
+    /// the folder will be created when the first session is stored
+    #[must_use]
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            config: SessionConfig::default(),
+            lock: Default::default(),
+        }
+    }
+
+    pub fn with_config(mut self, config: SessionConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// shorthand for setting `SessionConfig::same_site`
+    pub fn with_same_site(mut self, same_site: SameSite) -> Self {
+        self.config.same_site = same_site;
+        self
+    }
+
+    /// session ids are base64, which can contain `/`
+    fn session_path(&self, id: &str) -> PathBuf {
+        let mut path = self.path.clone();
+        path.push(format!("{}.json", id.replace('/', "_").replace('+', "-")));
+        path
+    }
+
+    /// the stored session, even if it's expired
+    async fn read(&self, id: &str) -> Result<Option<FileSession>, ErrResponse> {
+        match tokio::fs::read_to_string(self.session_path(id)).await {
+            Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// writes to a temporary file first, so readers never see half a session
+    async fn write(&self, stored: &FileSession) -> Result<(), ErrResponse> {
+        tokio::fs::create_dir_all(&self.path).await?;
+        let path = self.session_path(stored.session.id());
+        let tmp = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+        tokio::fs::write(&tmp, serde_json::to_string(stored)?).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SessionStore for FileStore {
+    async fn load_session(&self, cookie_value: String) -> Result<Option<Session>, ErrResponse> {
+        let id = id_from_cookie_value(&cookie_value)?;
+
+        match self.read(&id).await? {
+            Some(stored)
+                if stored.expires > Utc::now() && !self.config.is_expired(&stored.session) =>
+            {
+                Ok(Some(stored.session))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn store_session(&self, mut session: Session) -> Result<Option<String>, ErrResponse> {
+        let expires = self.config.prepare_for_store(&mut session)?;
+        let stored = FileSession { expires, session };
+
+        let _lock = self.lock.lock().await;
+        self.write(&stored).await?;
+
+        Ok(stored.session.into_cookie_value())
+    }
+
+    async fn store_changes(
+        &self,
+        session: Session,
+        changes: &SessionChanges,
+    ) -> Result<Option<String>, ErrResponse> {
+        // held from reading to writing, so nothing can be stored in between
+        let _lock = self.lock.lock().await;
+
+        let mut latest = match self.read(session.id()).await? {
+            Some(stored) if stored.expires > Utc::now() => stored.session,
+            // it was destroyed by another request
+            _ if changes.was_loaded() => return Ok(None),
+            _ => session.clone(),
+        };
+        changes.apply_to(&mut latest);
+
+        let expires = self.config.prepare_for_store(&mut latest)?;
+        self.write(&FileSession {
+            expires,
+            session: latest,
+        })
+        .await?;
+
+        Ok(session.into_cookie_value())
+    }
+
+    async fn destroy_session(&self, session: Session) -> Result<(), ErrResponse> {
+        let _lock = self.lock.lock().await;
+        match tokio::fs::remove_file(self.session_path(session.id())).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn cleanup(&self) -> Result<u64, ErrResponse> {
+        let mut dir = match tokio::fs::read_dir(&self.path).await {
+            Ok(dir) => dir,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err.into()),
+        };
+
+        let _lock = self.lock.lock().await;
+        let now = Utc::now();
+        let mut count = 0;
+        while let Some(entry) = dir.next_entry().await? {
+            // skips files being written by `write`
+            if entry.path().extension().is_some_and(|ext| ext == "tmp") {
+                continue;
+            }
+
+            // one bad file shouldn't stop the rest from being cleaned up
+            let contents = match tokio::fs::read_to_string(entry.path()).await {
+                Ok(contents) => contents,
+                Err(err) => {
+                    tracing::warn!("couldn't read session file {:?}: {err}", entry.path());
+                    continue;
+                }
+            };
+            match serde_json::from_str::<FileSession>(&contents) {
+                Ok(stored) if stored.expires > now => {}
+                Ok(_) => match tokio::fs::remove_file(entry.path()).await {
+                    Ok(()) => count += 1,
+                    Err(err) if err.kind() == ErrorKind::NotFound => {}
+                    Err(err) => {
+                        tracing::warn!("couldn't remove session file {:?}: {err}", entry.path())
+                    }
+                },
+                Err(err) => tracing::warn!("couldn't parse session file {:?}: {err}", entry.path()),
+            }
+        }
+
+        Ok(count)
+    }
+
+    fn config(&self) -> &SessionConfig {
+        &self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sessions::changes::session_data;
+    use chrono::Duration;
+
+    fn temp_store() -> FileStore {
+        let mut path = std::env::temp_dir();
+        path.push(format!("muxa-sessions-{}", uuid::Uuid::new_v4()));
+        FileStore::new(path)
+    }
+
+    #[tokio::test]
+    async fn test_store_load_and_destroy() {
+        let store = temp_store();
+        let mut session = Session::new();
+        session.insert("hey", "hello").unwrap();
+        let id = session.id().to_string();
+
+        let cookie = store.store_session(session).await.unwrap().unwrap();
+        let loaded = store.load_session(cookie.clone()).await.unwrap().unwrap();
+        assert_eq!(loaded.id(), id);
+        assert_eq!(loaded.get::<String>("hey"), Some("hello".to_string()));
+
+        store.destroy_session(loaded.clone()).await.unwrap();
+        assert!(store.load_session(cookie).await.unwrap().is_none());
+        // destroying twice is fine
+        store.destroy_session(loaded).await.unwrap();
+
+        tokio::fs::remove_dir_all(&store.path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_store_changes_merges() {
+        let store = temp_store();
+        let mut session = Session::new();
+        session.insert("a", 1).unwrap();
+        let cookie = store.store_session(session).await.unwrap().unwrap();
+
+        // two requests load the same session, and change different keys
+        let mut first = store.load_session(cookie.clone()).await.unwrap().unwrap();
+        let mut second = first.clone();
+        let original = session_data(&first);
+        first.insert("b", 2).unwrap();
+        second.insert("c", 3).unwrap();
+        let first_changes = SessionChanges::between(Some(&original), &first);
+        let second_changes = SessionChanges::between(Some(&original), &second);
+
+        store.store_changes(first, &first_changes).await.unwrap();
+        store.store_changes(second, &second_changes).await.unwrap();
+        let loaded = store.load_session(cookie.clone()).await.unwrap().unwrap();
+        assert_eq!(loaded.get::<i32>("b"), Some(2));
+        assert_eq!(loaded.get::<i32>("c"), Some(3));
+
+        // a request that finishes after a logout doesn't bring the session back
+        let mut late = loaded.clone();
+        store.destroy_session(loaded).await.unwrap();
+        late.insert("d", 4).unwrap();
+        let changes = SessionChanges::between(Some(&original), &late);
+        assert!(store.store_changes(late, &changes).await.unwrap().is_none());
+        assert!(store.load_session(cookie).await.unwrap().is_none());
+
+        tokio::fs::remove_dir_all(&store.path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_skips_bad_files() {
+        let store = temp_store();
+        let expired = store
+            .clone()
+            .with_config(SessionConfig::default().with_lifetime(Duration::seconds(-1)));
+
+        let expired_cookie = expired
+            .store_session(Session::new())
+            .await
+            .unwrap()
+            .unwrap();
+        let cookie = store.store_session(Session::new()).await.unwrap().unwrap();
+        tokio::fs::write(store.path.join("corrupt.json"), "{ nope")
+            .await
+            .unwrap();
+        tokio::fs::create_dir(store.path.join("unreadable"))
+            .await
+            .unwrap();
+
+        assert_eq!(store.cleanup().await.unwrap(), 1);
+        assert!(store.load_session(expired_cookie).await.unwrap().is_none());
+        assert!(store.load_session(cookie).await.unwrap().is_some());
+        assert_eq!(store.cleanup().await.unwrap(), 0);
+
+        tokio::fs::remove_dir_all(&store.path).await.unwrap();
+    }
+}
@@ -0,0 +1,66 @@
+/// how important a flash message is, usually mapped to a css class
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlashLevel {
+    Success,
+    Info,
+    Warning,
+    Error,
+}
+
+impl std::fmt::Display for FlashLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            FlashLevel::Success => write!(f, "success"),
+            FlashLevel::Info => write!(f, "info"),
+            FlashLevel::Warning => write!(f, "warning"),
+            FlashLevel::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// a message that is shown on the next request, see `UserSession::flash_message`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlashMessage {
+    pub level: FlashLevel,
+    /// optional identifier, eg to show the message next to a specific form
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl FlashMessage {
+    pub fn new(level: FlashLevel, message: impl ToString) -> Self {
+        Self {
+            level,
+            key: None,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn success(message: impl ToString) -> Self {
+        Self::new(FlashLevel::Success, message)
+    }
+
+    pub fn info(message: impl ToString) -> Self {
+        Self::new(FlashLevel::Info, message)
+    }
+
+    pub fn warning(message: impl ToString) -> Self {
+        Self::new(FlashLevel::Warning, message)
+    }
+
+    pub fn error(message: impl ToString) -> Self {
+        Self::new(FlashLevel::Error, message)
+    }
+
+    pub fn with_key(mut self, key: impl ToString) -> Self {
+        self.key = Some(key.to_string());
+        self
+    }
+}
+
+impl std::fmt::Display for FlashMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
@@ -0,0 +1,126 @@
+use async_session::Session;
+use axum::{extract::ConnectInfo, http::Request};
+use chrono::{DateTime, Utc};
+use http::header::USER_AGENT;
+use std::net::{IpAddr, SocketAddr};
+
+pub(crate) const USER_ID_KEY: &str = "internal-key-user-id";
+const USER_AGENT_KEY: &str = "internal-key-user-agent";
+const IP_KEY: &str = "internal-key-ip";
+/// longer user agents are cut off, they're only shown to the user anyway
+const MAX_USER_AGENT_LEN: usize = 512;
+
+/// a session belonging to a user, as listed by `DbSessionStore::sessions_for_user`
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct SessionInfo {
+    pub id: String,
+    pub created_at: Option<DateTime<Utc>>,
+    pub last_seen: Option<DateTime<Utc>>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub expires: DateTime<Utc>,
+}
+
+/// the device a request comes from
+///
+/// only used to show the user where they are logged in, it's not trustworthy
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ClientInfo {
+    user_agent: Option<String>,
+    ip: Option<String>,
+}
+
+impl ClientInfo {
+    pub(crate) fn from_request<B>(req: &Request<B>) -> Self {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(ToString::to_string)
+        };
+
+        // most apps run behind a reverse proxy, so the forwarded headers go first.
+        // anything that isn't an ip is ignored, so it always fits in the `ip` column
+        let ip = header("x-forwarded-for")
+            .and_then(|v| v.split(',').next()?.trim().parse::<IpAddr>().ok())
+            .or_else(|| header("x-real-ip")?.trim().parse::<IpAddr>().ok())
+            .or_else(|| {
+                req.extensions()
+                    .get::<ConnectInfo<SocketAddr>>()
+                    .map(|ConnectInfo(addr)| addr.ip())
+            });
+
+        let user_agent = header(USER_AGENT.as_str()).map(|mut user_agent| {
+            if user_agent.len() > MAX_USER_AGENT_LEN {
+                let mut end = MAX_USER_AGENT_LEN;
+                while !user_agent.is_char_boundary(end) {
+                    end -= 1;
+                }
+                user_agent.truncate(end);
+            }
+            user_agent
+        });
+
+        Self {
+            user_agent,
+            ip: ip.map(|ip| ip.to_string()),
+        }
+    }
+
+    /// should only be called when the session is going to be stored anyway
+    pub(crate) fn insert_into(&self, session: &mut Session) -> Result<(), serde_json::Error> {
+        if let Some(user_agent) = &self.user_agent {
+            session.insert(USER_AGENT_KEY, user_agent)?;
+        }
+        if let Some(ip) = &self.ip {
+            session.insert(IP_KEY, ip)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn from_session(session: &Session) -> Self {
+        Self {
+            user_agent: session.get(USER_AGENT_KEY),
+            ip: session.get(IP_KEY),
+        }
+    }
+
+    pub(crate) fn user_agent(&self) -> Option<&str> {
+        self.user_agent.as_deref()
+    }
+
+    pub(crate) fn ip(&self) -> Option<&str> {
+        self.ip.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+
+    fn client(headers: &[(&str, &str)]) -> ClientInfo {
+        let mut req = Request::builder();
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        let mut req = req.body(Body::empty()).unwrap();
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 80))));
+        ClientInfo::from_request(&req)
+    }
+
+    #[test]
+    fn test_client_info() {
+        let info = client(&[("x-forwarded-for", "1.2.3.4, 10.0.0.2")]);
+        assert_eq!(info.ip(), Some("1.2.3.4"));
+        let info = client(&[("x-forwarded-for", "::1")]);
+        assert_eq!(info.ip(), Some("::1"));
+
+        // garbage falls back to the peer address
+        let long = "a".repeat(1000);
+        let info = client(&[("x-forwarded-for", &long), ("user-agent", &long)]);
+        assert_eq!(info.ip(), Some("10.0.0.1"));
+        assert_eq!(info.user_agent().unwrap().len(), MAX_USER_AGENT_LEN);
+    }
+}
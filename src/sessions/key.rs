@@ -0,0 +1,41 @@
+use std::{fmt::Debug, marker::PhantomData};
+
+/// a session key with a single type, so every handler reads and writes the same thing
+///
+/// ```ignore
+/// const CART: SessionKey<Vec<u32>> = SessionKey::new("cart");
+///
+/// session.insert_typed(CART, &vec![1, 2]).await?;
+/// let cart = session.get_typed(CART)?.unwrap_or_default();
+/// ```
+pub struct SessionKey<T> {
+    name: &'static str,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T> SessionKey<T> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            _type: PhantomData,
+        }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+// derives would require `T: Clone`
+impl<T> Clone for SessionKey<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T> Copy for SessionKey<T> {}
+
+impl<T> Debug for SessionKey<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SessionKey").field(&self.name).finish()
+    }
+}
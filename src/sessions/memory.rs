@@ -0,0 +1,145 @@
+use async_session::Session;
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use super::{
+    store::{id_from_cookie_value, SessionStore},
+    SessionChanges, SessionConfig,
+};
+use crate::{cookies::SameSite, errors::ErrResponse};
+
+/// keeps sessions in memory, they are lost when the process exits
+///
+/// sessions are kept serialized, so that two requests never share the same
+/// session data by accident
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    sessions: Arc<RwLock<HashMap<String, MemorySession>>>,
+    config: SessionConfig,
+}
+
+struct MemorySession {
+    expires: DateTime<Utc>,
+    session: String,
+}
+
+impl MemoryStore {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_config(mut self, config: SessionConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// shorthand for setting `SessionConfig::same_site`
+    pub fn with_same_site(mut self, same_site: SameSite) -> Self {
+        self.config.same_site = same_site;
+        self
+    }
+
+    /// number of sessions currently stored, including expired ones
+    pub fn count(&self) -> usize {
+        self.sessions.read().unwrap().len()
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemoryStore {
+    async fn load_session(&self, cookie_value: String) -> Result<Option<Session>, ErrResponse> {
+        let id = id_from_cookie_value(&cookie_value)?;
+        let sessions = self.sessions.read().unwrap();
+
+        match sessions.get(&id) {
+            Some(stored) if stored.expires > Utc::now() => {
+                let session: Session = serde_json::from_str(&stored.session)?;
+                Ok(Some(session).filter(|s| !self.config.is_expired(s)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn store_session(&self, mut session: Session) -> Result<Option<String>, ErrResponse> {
+        let expires = self.config.prepare_for_store(&mut session)?;
+        let stored = MemorySession {
+            expires,
+            session: serde_json::to_string(&session)?,
+        };
+        self.sessions
+            .write()
+            .unwrap()
+            .insert(session.id().to_string(), stored);
+
+        Ok(session.into_cookie_value())
+    }
+
+    async fn store_changes(
+        &self,
+        session: Session,
+        changes: &SessionChanges,
+    ) -> Result<Option<String>, ErrResponse> {
+        // the lock is held from reading to writing, so nothing can be stored in between
+        let mut sessions = self.sessions.write().unwrap();
+
+        let mut latest: Session = match sessions.get(session.id()) {
+            Some(stored) if stored.expires > Utc::now() => serde_json::from_str(&stored.session)?,
+            _ if changes.was_loaded() => return Ok(None),
+            _ => session.clone(),
+        };
+        changes.apply_to(&mut latest);
+
+        let expires = self.config.prepare_for_store(&mut latest)?;
+        let stored = MemorySession {
+            expires,
+            session: serde_json::to_string(&latest)?,
+        };
+        sessions.insert(session.id().to_string(), stored);
+
+        Ok(session.into_cookie_value())
+    }
+
+    async fn destroy_session(&self, session: Session) -> Result<(), ErrResponse> {
+        self.sessions.write().unwrap().remove(session.id());
+        Ok(())
+    }
+
+    async fn cleanup(&self) -> Result<u64, ErrResponse> {
+        let now = Utc::now();
+        let mut sessions = self.sessions.write().unwrap();
+        let before = sessions.len();
+        sessions.retain(|_, stored| stored.expires > now);
+        Ok((before - sessions.len()) as u64)
+    }
+
+    fn config(&self) -> &SessionConfig {
+        &self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_store_and_load() {
+        let store = MemoryStore::new();
+        let mut session = Session::new();
+        session.insert("hey", "hello").unwrap();
+        let id = session.id().to_string();
+
+        let cookie = store.store_session(session).await.unwrap().unwrap();
+        let loaded = store.load_session(cookie.clone()).await.unwrap().unwrap();
+        assert_eq!(loaded.id(), id);
+        assert_eq!(loaded.get::<String>("hey"), Some("hello".to_string()));
+
+        store.destroy_session(loaded).await.unwrap();
+        assert!(store.load_session(cookie).await.unwrap().is_none());
+        assert_eq!(store.count(), 0);
+    }
+}
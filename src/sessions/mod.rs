@@ -0,0 +1,770 @@
+use async_session::Session;
+use axum::{
+    headers::{Cookie, HeaderMapExt},
+    http::{header::SET_COOKIE, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::IntoResponse,
+};
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+use validator::ValidationErrors;
+
+use crate::{
+    errors::{internal_error, ErrResponse},
+    helpers::random_token,
+    validation::flatten_errors,
+};
+
+mod cache;
+mod changes;
+mod config;
+#[cfg(feature = "cookie_store")]
+mod cookie;
+mod db;
+mod expiry;
+mod file;
+mod flash;
+mod info;
+mod key;
+mod memory;
+mod old;
+mod reaper;
+mod remember;
+mod store;
+
+pub use cache::CachedSessionStore;
+pub use changes::SessionChanges;
+pub use config::SessionConfig;
+#[cfg(feature = "cookie_store")]
+pub use cookie::{CookieStore, MAX_COOKIE_SIZE};
+pub use db::DbSessionStore;
+pub use expiry::ExpiryPolicy;
+pub use file::FileStore;
+pub use flash::{FlashLevel, FlashMessage};
+pub use info::SessionInfo;
+pub use key::SessionKey;
+pub use memory::MemoryStore;
+pub use old::OldInput;
+pub use reaper::SessionReaper;
+pub use remember::{RememberMe, REMEMBER_COOKIE_NAME};
+pub use store::SessionStore;
+
+use changes::session_data;
+use info::{ClientInfo, USER_ID_KEY};
+use remember::Remembered;
+
+// implemented following
+// https://github.com/tokio-rs/axum/blob/main/examples/sessions/src/main.rs
+// https://docs.rs/async-session/latest/src/async_session/memory_store.rs.html#17-52
+// https://github.com/AscendingCreations/AxumSessions/blob/main/src/session_store.rs
+// https://github.com/AscendingCreations/AxumSessions/blob/main/src/databases/mysql.rs
+
+/// default name for the session cookie, see `SessionConfig`
+pub const SESSION_COOKIE_NAME: &str = "muxa_session";
+
+const FLASH_KEY: &str = "internal-key-flash";
+const FLASH_KEY_TRACKER: &str = "internal-key-flash-tracker";
+const ERRORS_KEY: &str = "internal-key-errors";
+const ERRORS_KEY_TRACKER: &str = "internal-key-errors-tracker";
+const OLD_KEY: &str = "internal-key-old";
+const OLD_KEY_TRACKER: &str = "internal-key-old-tracker";
+const CSRF_KEY: &str = "internal-key-csrf-token";
+const REMEMBER_KEY_TRACKER: &str = "internal-key-remember-tracker";
+/// the remember-me token that logged in the session, so revoking the session can delete it
+const REMEMBER_SELECTOR_KEY: &str = "internal-key-remember-selector";
+const TWO_FACTOR_PENDING_KEY: &str = "internal-key-two-factor-pending";
+/// seconds users have to enter their two-factor code before having to log in again
+const TWO_FACTOR_PENDING_LIFETIME: i64 = 10 * 60;
+
+#[derive(Serialize, serde::Deserialize)]
+struct TwoFactorPending {
+    user_id: String,
+    /// unix timestamp
+    started_at: i64,
+}
+
+/// clones share the same session, so a handler calling `regenerate` or `destroy`
+/// is seen by `session_middleware`
+#[derive(Clone)]
+pub struct UserSession {
+    session: Arc<Mutex<Session>>,
+    store: Arc<dyn SessionStore>,
+}
+impl std::fmt::Debug for UserSession {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UserSession")
+            .field("session", &self.session)
+            .field("store", &"SessionStore".to_string())
+            .finish()
+    }
+}
+
+impl UserSession {
+    fn new(session: Session, store: Arc<dyn SessionStore>) -> Self {
+        Self {
+            session: Arc::new(Mutex::new(session)),
+            store,
+        }
+    }
+
+    /// the lock is never held across an await point
+    fn session(&self) -> MutexGuard<'_, Session> {
+        self.session.lock().unwrap()
+    }
+
+    /// stores the session in the SessionStore
+    /// returns the header value which will set the corresponding cookie
+    ///
+    /// only `changes` are stored, on top of the latest version of the session
+    /// if another request destroyed the session in the meantime, the cookie is expired instead
+    ///
+    /// this takes the original session out, so it should only be called once,
+    /// at the end of the request
+    async fn save_and_get_cookie(
+        self,
+        changes: SessionChanges,
+    ) -> Result<HeaderValue, ErrResponse> {
+        let session = std::mem::take(&mut *self.session());
+        let max_age = (self.store.config().expiry_for(&session) - Utc::now()).num_seconds();
+        match self.store.store_changes(session, &changes).await? {
+            Some(cookie) => self.cookie_header(&cookie, max_age),
+            None => self.expired_cookie(),
+        }
+    }
+
+    /// returns the header value which will remove the session cookie
+    fn expired_cookie(&self) -> Result<HeaderValue, ErrResponse> {
+        self.cookie_header("", 0)
+    }
+
+    fn cookie_header(&self, value: &str, max_age: i64) -> Result<HeaderValue, ErrResponse> {
+        HeaderValue::from_str(&self.store.config().cookie(value, max_age)).map_err(internal_error)
+    }
+
+    /// stores the session in the SessionStore right away
+    ///
+    /// usually not needed, `session_middleware` stores changed sessions at the end of the request
+    pub async fn save(&self) -> Result<(), ErrResponse> {
+        // when we clone the session, the cookie value is not set, so we can ignore it
+        let session = self.session().clone();
+        let _cookie = self.store.store_session(session).await?;
+        Ok(())
+    }
+
+    /// gives the session a new id, keeping its data, and deletes the old one from the store.
+    /// the absolute lifetime starts over from here
+    ///
+    /// should be called when logging in, to prevent session fixation
+    pub async fn regenerate(&mut self) -> Result<(), ErrResponse> {
+        let old = {
+            let mut session = self.session();
+            let old = session.clone();
+            session.regenerate();
+            expiry::reset_created_at(&mut session);
+            old
+        };
+        self.store.destroy_session(old).await
+    }
+
+    /// deletes the session from the store, and makes `session_middleware` expire the cookie
+    ///
+    /// anything inserted afterwards during this request is discarded
+    pub async fn destroy(&mut self) -> Result<(), ErrResponse> {
+        let session = {
+            let mut session = self.session();
+            session.destroy();
+            session.clone()
+        };
+        self.store.destroy_session(session).await
+    }
+
+    /// overrides `SessionConfig::expiry` for this session, eg for remember-me logins
+    pub async fn set_expiry_policy(&mut self, policy: ExpiryPolicy) -> Result<(), ErrResponse> {
+        policy.insert_into(&mut self.session())?;
+        Ok(())
+    }
+
+    /// the session id, as stored in `DbSessionStore`
+    /// it's not the cookie value, so it's safe to show it to the user
+    pub fn id(&self) -> String {
+        self.session().id().to_string()
+    }
+
+    /// links the session to a user, so `DbSessionStore` can list and revoke their sessions
+    pub async fn set_user_id(&mut self, user_id: impl ToString) -> Result<(), ErrResponse> {
+        self.session().insert(USER_ID_KEY, user_id.to_string())?;
+        Ok(())
+    }
+
+    pub fn user_id(&self) -> Option<String> {
+        self.session().get(USER_ID_KEY)
+    }
+
+    pub async fn remove_user_id(&mut self) -> Result<(), ErrResponse> {
+        self.session().remove(USER_ID_KEY);
+        Ok(())
+    }
+
+    /// the user who passed the password check, but still has to pass two-factor
+    /// they aren't logged in yet, so `user_id` returns `None`, see `auth::TwoFactor`
+    ///
+    /// it only lasts 10 minutes, after that they have to enter their password again
+    pub fn two_factor_pending(&self) -> Option<String> {
+        let pending: TwoFactorPending = self.session().get(TWO_FACTOR_PENDING_KEY)?;
+        (pending.started_at + TWO_FACTOR_PENDING_LIFETIME > Utc::now().timestamp())
+            .then_some(pending.user_id)
+    }
+
+    pub async fn set_two_factor_pending(
+        &mut self,
+        user_id: impl ToString,
+    ) -> Result<(), ErrResponse> {
+        let pending = TwoFactorPending {
+            user_id: user_id.to_string(),
+            started_at: Utc::now().timestamp(),
+        };
+        self.session().insert(TWO_FACTOR_PENDING_KEY, pending)?;
+        Ok(())
+    }
+
+    pub async fn remove_two_factor_pending(&mut self) -> Result<(), ErrResponse> {
+        self.session().remove(TWO_FACTOR_PENDING_KEY);
+        Ok(())
+    }
+
+    /// name of the session cookie, from the store's config
+    pub(crate) fn cookie_name(&self) -> String {
+        self.store.config().name.clone()
+    }
+
+    /// the selector of the `RememberMe` token issued to this session
+    pub(crate) fn remember_selector(&self) -> Option<String> {
+        self.session().get(REMEMBER_SELECTOR_KEY)
+    }
+
+    /// keeps the user logged in after the session expires, with a `RememberMe` token
+    /// should be called right after `auth::login`
+    pub async fn remember_me(&mut self) -> Result<(), ErrResponse> {
+        self.session().insert(REMEMBER_KEY_TRACKER, true)?;
+        Ok(())
+    }
+
+    /// the token forms have to send back, see `muxa::csrf`
+    /// it's created the first time it's needed, so sessions that never render a form aren't stored
+    pub fn csrf_token(&self) -> String {
+        let mut session = self.session();
+        match session.get(CSRF_KEY) {
+            Some(token) => token,
+            None => {
+                let token = random_token(32);
+                session
+                    .insert(CSRF_KEY, &token)
+                    .expect("strings can always be serialized");
+                token
+            }
+        }
+    }
+
+    /// replaces the csrf token, forms rendered before this will be rejected
+    pub async fn regenerate_csrf_token(&mut self) -> Result<(), ErrResponse> {
+        self.session().insert(CSRF_KEY, random_token(32))?;
+        Ok(())
+    }
+
+    pub fn is_destroyed(&self) -> bool {
+        self.session().is_destroyed()
+    }
+
+    pub async fn insert(
+        &mut self,
+        key: &str,
+        value: impl serde::Serialize,
+    ) -> Result<(), ErrResponse> {
+        self.session().insert(key, value)?;
+        Ok(())
+    }
+
+    pub fn get<T: serde::de::DeserializeOwned>(&self, key: &str) -> Option<T> {
+        self.session().get(key)
+    }
+
+    pub async fn remove(&mut self, key: &str) -> Result<(), ErrResponse> {
+        self.session().remove(key);
+        Ok(())
+    }
+
+    /// returns an error if the stored value can't be deserialized into `T`
+    pub fn get_typed<T: serde::de::DeserializeOwned>(
+        &self,
+        key: SessionKey<T>,
+    ) -> Result<Option<T>, ErrResponse> {
+        let raw = match self.session().get_raw(key.name()) {
+            Some(raw) => raw,
+            None => return Ok(None),
+        };
+        serde_json::from_str(&raw).map(Some).map_err(|err| {
+            ErrResponse::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("couldn't deserialize session key `{}`: {err}", key.name()),
+            )
+        })
+    }
+
+    pub async fn insert_typed<T: Serialize>(
+        &mut self,
+        key: SessionKey<T>,
+        value: &T,
+    ) -> Result<(), ErrResponse> {
+        self.session().insert(key.name(), value)?;
+        Ok(())
+    }
+
+    /// removes the value from the session and returns it
+    pub async fn take<T: serde::de::DeserializeOwned>(
+        &mut self,
+        key: SessionKey<T>,
+    ) -> Result<Option<T>, ErrResponse> {
+        let value = self.get_typed(key)?;
+        self.session().remove(key.name());
+        Ok(value)
+    }
+
+    /// adds an info message to show on the next request
+    pub async fn flash(&mut self, value: impl AsRef<str>) -> Result<(), ErrResponse> {
+        self.flash_message(FlashMessage::info(value.as_ref())).await
+    }
+
+    /// adds a message to show on the next request
+    /// messages flashed during the same request are all kept, in order
+    pub async fn flash_message(&mut self, message: FlashMessage) -> Result<(), ErrResponse> {
+        let mut session = self.session();
+        // messages without the tracker are from the previous request, and are being shown now
+        let mut messages: Vec<FlashMessage> = if session.get(FLASH_KEY_TRACKER) == Some(true) {
+            session.get(FLASH_KEY).unwrap_or_default()
+        } else {
+            vec![]
+        };
+        messages.push(message);
+        session.insert(FLASH_KEY, messages)?;
+        session.insert(FLASH_KEY_TRACKER, true)?;
+        Ok(())
+    }
+
+    pub fn get_flash(&self) -> Vec<FlashMessage> {
+        self.session().get(FLASH_KEY).unwrap_or_default()
+    }
+
+    // lmao
+    pub async fn errors<M, L, K, V>(&mut self, value: M) -> Result<(), ErrResponse>
+    where
+        M: Into<HashMap<K, L>>,
+        K: ToString,
+        L: Into<Vec<V>>,
+        V: ToString,
+    {
+        let map: HashMap<K, L> = value.into();
+        let map: HashMap<String, Vec<String>> = map
+            .into_iter()
+            .map(|(k, v)| {
+                (
+                    k.to_string(),
+                    v.into().iter().map(ToString::to_string).collect::<Vec<_>>(),
+                )
+            })
+            .collect();
+        self.session().insert(ERRORS_KEY, map)?;
+        self.session().insert(ERRORS_KEY_TRACKER, true)?;
+        Ok(())
+    }
+
+    /// stores the errors of nested structs and lists too, keyed by their path,
+    /// eg `address.city` or `items[2].qty`, see `validation::flatten_errors`
+    pub async fn validation_errors(&mut self, value: ValidationErrors) -> Result<(), ErrResponse> {
+        self.errors(flatten_errors(&value)).await
+    }
+
+    pub async fn get_errors(&mut self) -> Result<HashMap<String, Vec<String>>, ErrResponse> {
+        Ok(self.session().get(ERRORS_KEY).unwrap_or_default())
+    }
+
+    pub async fn old<T: Serialize>(&mut self, old: T) -> Result<(), ErrResponse> {
+        self.session().insert(OLD_KEY, old)?;
+        self.session().insert(OLD_KEY_TRACKER, true)?;
+        Ok(())
+    }
+
+    /// the input stored with `old` by the previous request, with arrays and nested values kept
+    pub fn get_old(&mut self) -> Result<OldInput, ErrResponse> {
+        let old: Value = self.session().get(OLD_KEY).unwrap_or_default();
+        Ok(OldInput::new(old))
+    }
+}
+
+async fn get_session_from_cookie(
+    store: &dyn SessionStore,
+    cookie: Option<String>,
+) -> Option<Session> {
+    let c = cookie?;
+    match store.load_session(c.clone()).await {
+        Ok(Some(mut session)) => {
+            session.set_cookie_value(c);
+            Some(session)
+        }
+        Ok(None) => None,
+        Err(err) => {
+            tracing::error!("error getting session {err:?}");
+            None
+        }
+    }
+}
+
+/// an empty `value` removes the cookie
+fn remember_cookie_header(remember: &RememberMe, value: &str) -> Result<HeaderValue, ErrResponse> {
+    let config = remember.config();
+    let max_age = if value.is_empty() {
+        0
+    } else {
+        config.lifetime.num_seconds()
+    };
+    HeaderValue::from_str(&config.cookie(value, max_age)).map_err(internal_error)
+}
+
+/// returns true if `touch_after` has passed since the session was last stored
+fn needs_touch(session: &Session, config: &SessionConfig) -> bool {
+    match (config.touch_after_for(session), expiry::last_seen(session)) {
+        (Some(touch_after), Some(last_seen)) => last_seen + touch_after <= Utc::now(),
+        // loaded sessions stored before timestamps were tracked
+        (Some(_), None) => true,
+        (None, _) => false,
+    }
+}
+
+/// loads the session from the `S` extension, and stores it once the request is done
+///
+/// `S` is usually `DbSessionStore`, see `default_layers!`
+pub async fn session_middleware<S, B>(mut req: Request<B>, next: Next<B>) -> impl IntoResponse
+where
+    S: SessionStore + Clone,
+{
+    tracing::trace!("starting request");
+    let store: Arc<dyn SessionStore> = Arc::new(
+        req.extensions()
+            .get::<S>()
+            .expect("`SessionStore` extension missing")
+            .clone(),
+    );
+
+    let cookies = req.headers().typed_get::<Cookie>();
+    let session_cookie = cookies
+        .as_ref()
+        .and_then(|c| c.get(&store.config().name))
+        .map(|a| a.to_string());
+
+    let remember = req.extensions().get::<RememberMe>().cloned();
+    let remember_cookie = remember.as_ref().and_then(|r| {
+        cookies
+            .as_ref()?
+            .get(&r.config().name)
+            .map(|a| a.to_string())
+    });
+    // value for the remember-me cookie, if it has to change
+    let mut new_remember_cookie = None;
+
+    // get the session and add it as a req extension
+    let (mut session, original) =
+        match get_session_from_cookie(store.as_ref(), session_cookie).await {
+            Some(session) => {
+                // what the session looked like, to find out which keys the request changed
+                let original = session_data(&session);
+                (session, Some(original))
+            }
+            None => {
+                tracing::trace!("no session found, creating new");
+                (Session::new(), None)
+            }
+        };
+
+    // the session is gone, but the user asked to be remembered
+    if let (None, Some(remember), Some(cookie)) = (&original, &remember, &remember_cookie) {
+        match remember.consume(cookie).await {
+            Ok(Remembered::User {
+                user_id,
+                cookie: new_cookie,
+            }) => {
+                session.insert(USER_ID_KEY, user_id)?;
+                session.insert(REMEMBER_SELECTOR_KEY, remember::selector(cookie))?;
+                new_remember_cookie = new_cookie;
+            }
+            Ok(Remembered::Invalid) => new_remember_cookie = Some(String::new()),
+            Err(err) => tracing::error!("error using remember-me token {err:?}"),
+        }
+    }
+
+    let original_id = session.id().to_string();
+    let client = ClientInfo::from_request(&req);
+    let user_session = UserSession::new(session, store);
+    req.extensions_mut().insert(user_session.clone());
+
+    // keep going and get the response
+    let mut res = next.run(req).await;
+
+    if user_session.is_destroyed() {
+        res.headers_mut()
+            .insert(SET_COOKIE, user_session.expired_cookie()?);
+        if let (Some(remember), Some(cookie)) = (&remember, &remember_cookie) {
+            remember.forget(cookie).await?;
+            res.headers_mut()
+                .append(SET_COOKIE, remember_cookie_header(remember, "")?);
+        }
+        tracing::trace!("ending request");
+        return Ok(res);
+    }
+
+    let remember_user = {
+        let mut session = user_session.session();
+        if session.get(REMEMBER_KEY_TRACKER) == Some(true) {
+            session.remove(REMEMBER_KEY_TRACKER);
+            session.get::<String>(USER_ID_KEY)
+        } else {
+            None
+        }
+    };
+    if let Some(user_id) = remember_user {
+        match &remember {
+            Some(remember) => {
+                if let Some(cookie) = &remember_cookie {
+                    remember.forget(cookie).await?;
+                }
+                let cookie = remember.issue(&user_id).await?;
+                user_session
+                    .session()
+                    .insert(REMEMBER_SELECTOR_KEY, remember::selector(&cookie))?;
+                new_remember_cookie = Some(cookie);
+            }
+            None => {
+                tracing::warn!("`remember_me` was called, but there is no `RememberMe` extension")
+            }
+        }
+    }
+
+    // to make sure we don't delete the flash and errors we just set, we check the tracker
+    let hard_coded_keys = [
+        (FLASH_KEY, FLASH_KEY_TRACKER),
+        (ERRORS_KEY, ERRORS_KEY_TRACKER),
+        (OLD_KEY, OLD_KEY_TRACKER),
+    ];
+    let changes = {
+        let mut session = user_session.session();
+        for (k, t) in hard_coded_keys {
+            if session.get(t) != Some(true) {
+                session.remove(k);
+            }
+            session.remove(t);
+        }
+
+        // unchanged sessions are not stored, so new sessions without data never reach the store
+        let should_store = session.data_changed()
+            || session.id() != original_id
+            || (original.is_some() && needs_touch(&session, user_session.store.config()));
+
+        if should_store {
+            client.insert_into(&mut session)?;
+            // a regenerated session is stored as a whole under its new id
+            let original = original.as_ref().filter(|_| session.id() == original_id);
+            Some(SessionChanges::between(original, &session))
+        } else {
+            None
+        }
+    };
+
+    if let Some(changes) = changes {
+        // consume the session and get the cookie value
+        let cookie = user_session.save_and_get_cookie(changes).await?;
+        res.headers_mut().insert(SET_COOKIE, cookie);
+    }
+
+    if let (Some(remember), Some(cookie)) = (&remember, new_remember_cookie) {
+        res.headers_mut()
+            .append(SET_COOKIE, remember_cookie_header(remember, &cookie)?);
+    }
+
+    tracing::trace!("ending request");
+    Ok::<_, ErrResponse>(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::helpers::*;
+    use axum::{extract::Extension, routing::get, Router};
+
+    fn app(store: MemoryStore) -> Router {
+        Router::new()
+            .route("/", get(|| async { "ok" }))
+            .route(
+                "/login",
+                get(|mut session: Extension<UserSession>| async move {
+                    session.insert("user", 1).await?;
+                    session.regenerate().await?;
+                    Ok::<_, ErrResponse>("ok")
+                }),
+            )
+            .route(
+                "/logout",
+                get(|mut session: Extension<UserSession>| async move {
+                    session.destroy().await?;
+                    Ok::<_, ErrResponse>("ok")
+                }),
+            )
+            .layer(axum::middleware::from_fn(
+                session_middleware::<MemoryStore, _>,
+            ))
+            .layer(Extension(store))
+    }
+
+    #[tokio::test]
+    async fn test_regenerate_and_destroy() {
+        let store = MemoryStore::new();
+
+        let res = app(store.clone()).req(empty_get("/login")).await;
+        let first = session_cookie(&res);
+
+        let res = app(store.clone()).req(with_cookie("/login", &first)).await;
+        let second = session_cookie(&res);
+        assert_ne!(first, second);
+        // the old session was deleted, and the data moved to the new one
+        assert_eq!(store.count(), 1);
+
+        let res = app(store.clone())
+            .req(with_cookie("/logout", &second))
+            .await;
+        assert_eq!(session_cookie(&res), format!("{SESSION_COOKIE_NAME}="));
+        assert!(res.parts.headers[SET_COOKIE]
+            .to_str()
+            .unwrap()
+            .contains("Max-Age=0"));
+        assert_eq!(store.count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_typed_keys() {
+        const COUNT: SessionKey<u32> = SessionKey::new("count");
+        const WRONG: SessionKey<Vec<String>> = SessionKey::new("count");
+
+        let mut session = UserSession::new(Session::new(), Arc::new(MemoryStore::new()));
+        assert_eq!(session.get_typed(COUNT).unwrap(), None);
+
+        session.insert_typed(COUNT, &3).await.unwrap();
+        assert_eq!(session.get_typed(COUNT).unwrap(), Some(3));
+        assert!(session.get_typed(WRONG).is_err());
+
+        assert_eq!(session.take(COUNT).await.unwrap(), Some(3));
+        assert_eq!(session.get_typed(COUNT).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_flash_messages() {
+        let mut session = UserSession::new(Session::new(), Arc::new(MemoryStore::new()));
+        session.flash("saved").await.unwrap();
+        session
+            .flash_message(FlashMessage::error("email failed").with_key("email"))
+            .await
+            .unwrap();
+        assert_eq!(
+            session.get_flash(),
+            vec![
+                FlashMessage::info("saved"),
+                FlashMessage::error("email failed").with_key("email")
+            ]
+        );
+
+        // what `session_middleware` does at the end of the request
+        session.session().remove(FLASH_KEY_TRACKER);
+        session.flash("new").await.unwrap();
+        assert_eq!(session.get_flash(), vec![FlashMessage::info("new")]);
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_sessions_are_not_stored() {
+        let store = MemoryStore::new();
+
+        let res = app(store.clone()).req(empty_get("/")).await;
+        assert!(res.parts.headers.get(SET_COOKIE).is_none());
+        assert_eq!(store.count(), 0);
+
+        let res = app(store.clone()).req(empty_get("/login")).await;
+        let cookie = session_cookie(&res);
+
+        let res = app(store.clone()).req(with_cookie("/", &cookie)).await;
+        assert!(res.parts.headers.get(SET_COOKIE).is_none());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_remember_me() {
+        let store = MemoryStore::new();
+        let remember = RememberMe::new(crate::db::test_pool().await);
+        let app = || {
+            Router::new()
+                .route(
+                    "/remember",
+                    get(|mut session: Extension<UserSession>| async move {
+                        session.set_user_id(1).await?;
+                        session.remember_me().await?;
+                        Ok::<_, ErrResponse>("ok")
+                    }),
+                )
+                .route(
+                    "/whoami",
+                    get(|session: Extension<UserSession>| async move {
+                        session.user_id().unwrap_or_default()
+                    }),
+                )
+                .route(
+                    "/logout",
+                    get(|mut session: Extension<UserSession>| async move {
+                        session.destroy().await?;
+                        Ok::<_, ErrResponse>("ok")
+                    }),
+                )
+                .layer(axum::middleware::from_fn(
+                    session_middleware::<MemoryStore, _>,
+                ))
+                .layer(Extension(store.clone()))
+                .layer(Extension(remember.clone()))
+        };
+        let cookies = |res: &TestResponse| -> Vec<String> {
+            res.parts
+                .headers
+                .get_all(SET_COOKIE)
+                .iter()
+                .map(|h| h.to_str().unwrap().split(';').next().unwrap().to_string())
+                .collect()
+        };
+
+        let res = app().req(empty_get("/remember")).await;
+        let remember_cookie = cookies(&res)[1].clone();
+        assert!(remember_cookie.starts_with(REMEMBER_COOKIE_NAME));
+
+        // the session is gone, but the remember-me cookie logs the user back in
+        let res = app().req(with_cookie("/whoami", &remember_cookie)).await;
+        assert!(res.contains_str("1"));
+        let [session_cookie, rotated]: [String; 2] = cookies(&res).try_into().unwrap();
+        assert_ne!(rotated, remember_cookie);
+
+        let res = app()
+            .req(with_cookie(
+                "/logout",
+                &format!("{session_cookie}; {rotated}"),
+            ))
+            .await;
+        assert_eq!(cookies(&res)[1], format!("{REMEMBER_COOKIE_NAME}="));
+        let res = app().req(with_cookie("/whoami", &rotated)).await;
+        assert!(res.doesnt_contain_str("1"));
+    }
+}
@@ -0,0 +1,104 @@
+use serde_json::Value;
+
+/// the previous request's input, as stored by `UserSession::old`
+///
+/// fields are looked up by path, with `.` between names and array indices,
+/// eg `address.city` or `items.0.qty`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OldInput(Value);
+
+impl OldInput {
+    pub(crate) fn new(value: Value) -> Self {
+        Self(value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match &self.0 {
+            Value::Null => true,
+            Value::Object(o) => o.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// the raw value at `path`
+    pub fn get(&self, path: &str) -> Option<&Value> {
+        // keys that contain dots themselves, eg from `HashMap<String, String>` inputs
+        if let Some(v) = self.0.get(path) {
+            return Some(v);
+        }
+
+        path.split('.')
+            .try_fold(&self.0, |value, segment| match value {
+                Value::Object(o) => o.get(segment),
+                Value::Array(a) => a.get(segment.parse::<usize>().ok()?),
+                _ => None,
+            })
+    }
+
+    /// the value at `path` as a string, for text inputs
+    /// returns `None` for missing fields, nulls, arrays and objects
+    pub fn get_str(&self, path: &str) -> Option<String> {
+        self.get(path).and_then(scalar_to_string)
+    }
+
+    /// every value at `path`, for checkboxes and multi-selects
+    /// a single value is returned as a list of one
+    pub fn get_all(&self, path: &str) -> Vec<String> {
+        match self.get(path) {
+            Some(Value::Array(a)) => a.iter().filter_map(scalar_to_string).collect(),
+            Some(v) => scalar_to_string(v).into_iter().collect(),
+            None => vec![],
+        }
+    }
+
+    /// returns true if `value` was submitted for `path`,
+    /// to know which checkbox or option to mark as checked/selected
+    pub fn contains(&self, path: &str, value: &str) -> bool {
+        self.get_all(path).iter().any(|v| v == value)
+    }
+
+    pub fn into_value(self) -> Value {
+        self.0
+    }
+}
+
+fn scalar_to_string(value: &Value) -> Option<String> {
+    match value {
+        // if we do to_string directly, strings get "" around them
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        Value::Null | Value::Array(_) | Value::Object(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_lookup() {
+        let old = OldInput::new(json!({
+            "name": "annie",
+            "age": 3,
+            "tags": ["a", "b"],
+            "address": { "city": "barcelona" },
+            "items": [{ "qty": 1 }, { "qty": 2 }],
+            "dotted.key": "yes",
+        }));
+
+        assert_eq!(old.get_str("name").as_deref(), Some("annie"));
+        assert_eq!(old.get_str("age").as_deref(), Some("3"));
+        assert_eq!(old.get_str("tags"), None);
+        assert_eq!(old.get_all("tags"), vec!["a", "b"]);
+        assert_eq!(old.get_all("name"), vec!["annie"]);
+        assert!(old.contains("tags", "b"));
+        assert!(!old.contains("tags", "c"));
+        assert_eq!(old.get_str("address.city").as_deref(), Some("barcelona"));
+        assert_eq!(old.get_str("items.1.qty").as_deref(), Some("2"));
+        assert_eq!(old.get_str("items.2.qty"), None);
+        assert_eq!(old.get_str("dotted.key").as_deref(), Some("yes"));
+        assert!(old.get_all("missing").is_empty());
+    }
+}
@@ -0,0 +1,107 @@
+use std::time::Duration;
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+use super::DbSessionStore;
+use crate::errors::ErrResponse;
+
+/// periodically deletes expired sessions from a `DbSessionStore`
+///
+/// ```ignore
+/// SessionReaper::new(DbSessionStore::new(pool.clone()))
+///     .with_interval(Duration::from_secs(60 * 60))
+///     .with_batch_size(500)
+///     .spawn();
+/// ```
+#[derive(Clone)]
+pub struct SessionReaper {
+    store: DbSessionStore,
+    interval: Duration,
+    batch_size: u32,
+}
+
+impl SessionReaper {
+    /// runs every hour, deleting 1000 sessions at a time
+    #[must_use]
+    pub fn new(store: DbSessionStore) -> Self {
+        Self {
+            store,
+            interval: Duration::from_secs(60 * 60),
+            batch_size: 1000,
+        }
+    }
+
+    /// how often expired sessions get deleted, at least once a second
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        // `tokio::time::interval` panics on zero, which would kill the task silently
+        self.interval = interval.max(Duration::from_secs(1));
+        self
+    }
+
+    /// how many sessions get deleted per query
+    pub fn with_batch_size(mut self, batch_size: u32) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// deletes expired sessions in batches until there are none left
+    /// returns how many were deleted
+    pub async fn run_once(&self) -> Result<u64, ErrResponse> {
+        let mut total = 0;
+        loop {
+            let deleted = self.store.cleanup_batch(self.batch_size).await?;
+            total += deleted;
+            if deleted < u64::from(self.batch_size) {
+                return Ok(total);
+            }
+            // let other queries through between batches
+            tokio::task::yield_now().await;
+        }
+    }
+
+    /// spawns a task that calls `run_once` every `interval`, starting right away
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                interval.tick().await;
+                match self.run_once().await {
+                    Ok(0) => tracing::debug!("no expired sessions to purge"),
+                    Ok(purged) => tracing::info!(purged, "purged expired sessions"),
+                    Err(err) => tracing::error!("error purging expired sessions {err:?}"),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::{
+        db::test_pool,
+        sessions::{SessionConfig, SessionStore},
+    };
+    use async_session::Session;
+
+    #[tokio::test]
+    async fn test_run_once_in_batches() {
+        let store = DbSessionStore::new(test_pool().await);
+        let expired = store
+            .clone()
+            .with_config(SessionConfig::default().with_lifetime(chrono::Duration::seconds(-1)));
+        for _ in 0..5 {
+            expired.store_session(Session::new()).await.unwrap();
+        }
+        let cookie = store.store_session(Session::new()).await.unwrap().unwrap();
+
+        let reaper = SessionReaper::new(store.clone())
+            .with_batch_size(2)
+            .with_interval(Duration::ZERO);
+        assert_eq!(reaper.interval, Duration::from_secs(1));
+        assert_eq!(reaper.run_once().await.unwrap(), 5);
+        assert_eq!(reaper.run_once().await.unwrap(), 0);
+        assert!(store.load_session(cookie).await.unwrap().is_some());
+    }
+}
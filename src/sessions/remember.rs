@@ -0,0 +1,269 @@
+use chrono::{DateTime, Duration, Utc};
+
+use super::SessionConfig;
+use crate::{
+    db::{sql, DbPool},
+    errors::ErrResponse,
+    helpers::{constant_time_eq, hash_token, random_token},
+};
+
+/// default name for the remember-me cookie
+pub const REMEMBER_COOKIE_NAME: &str = "muxa_remember";
+
+/// a token that was just replaced is still accepted for this long,
+/// so that parallel requests with the old cookie don't look like theft
+const ROTATION_GRACE_SECONDS: i64 = 30;
+
+/// keeps users logged in after their session expires, with a long-lived cookie
+///
+/// the cookie contains a selector, used to find the token, and a validator,
+/// which is only stored hashed in the `remember_tokens` table (see `muxa::db::migrate`).
+/// the validator changes every time the token is used, so if an old validator shows up,
+/// the token was stolen, and all of the user's tokens are deleted
+///
+/// add it as an extension, and call `UserSession::remember_me` after logging in.
+/// `session_middleware` then logs the user back in when their session is gone
+#[derive(Clone)]
+pub struct RememberMe {
+    pool: DbPool,
+    config: SessionConfig,
+}
+
+#[derive(sqlx::FromRow)]
+struct RememberToken {
+    validator_hash: String,
+    previous_hash: Option<String>,
+    rotated_at: Option<DateTime<Utc>>,
+    user_id: String,
+}
+
+/// the part of the cookie that identifies the token, it doesn't change when it's rotated
+pub(crate) fn selector(cookie: &str) -> &str {
+    cookie
+        .split_once(':')
+        .map_or(cookie, |(selector, _)| selector)
+}
+
+/// result of using a remember-me cookie
+pub(crate) enum Remembered {
+    /// `cookie` is the new cookie value, if the token was rotated
+    User {
+        user_id: String,
+        cookie: Option<String>,
+    },
+    Invalid,
+}
+
+impl RememberMe {
+    /// tokens last 30 days
+    #[must_use]
+    pub fn new(pool: DbPool) -> Self {
+        Self {
+            pool,
+            config: SessionConfig::default()
+                .with_name(REMEMBER_COOKIE_NAME)
+                .with_lifetime(Duration::days(30)),
+        }
+    }
+
+    /// cookie attributes, like `secure` and `domain`
+    ///
+    /// the name and lifetime aren't taken from `config`, since it's usually the session's,
+    /// and the cookies would overwrite each other. see `with_name` and `with_lifetime`
+    pub fn with_config(mut self, config: SessionConfig) -> Self {
+        self.config = SessionConfig {
+            name: self.config.name,
+            lifetime: self.config.lifetime,
+            ..config
+        };
+        self
+    }
+
+    /// name of the cookie, `muxa_remember` by default
+    pub fn with_name(mut self, name: impl ToString) -> Self {
+        self.config.name = name.to_string();
+        self
+    }
+
+    /// how long tokens last
+    pub fn with_lifetime(mut self, lifetime: Duration) -> Self {
+        self.config.lifetime = lifetime;
+        self
+    }
+
+    pub fn config(&self) -> &SessionConfig {
+        &self.config
+    }
+
+    /// creates a token for the user, and returns the cookie value
+    pub(crate) async fn issue(&self, user_id: &str) -> Result<String, ErrResponse> {
+        let selector = random_token(12);
+        let validator = random_token(32);
+
+        let q = sql(
+            "INSERT INTO remember_tokens (selector, validator_hash, user_id, expires)
+            VALUES (?, ?, ?, ?)",
+        );
+        sqlx::query(&q)
+            .bind(&selector)
+            .bind(hash_token(&validator))
+            .bind(user_id)
+            .bind(Utc::now() + self.config.lifetime)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(format!("{selector}:{validator}"))
+    }
+
+    /// checks the cookie, and rotates the token
+    pub(crate) async fn consume(&self, cookie: &str) -> Result<Remembered, ErrResponse> {
+        let (selector, validator) = match cookie.split_once(':') {
+            Some(parts) => parts,
+            None => return Ok(Remembered::Invalid),
+        };
+
+        let q = sql(
+            "SELECT validator_hash, previous_hash, rotated_at, user_id FROM remember_tokens
+            WHERE selector = ? AND expires > ?",
+        );
+        let token: Option<RememberToken> = sqlx::query_as(&q)
+            .bind(selector)
+            .bind(Utc::now())
+            .fetch_optional(&self.pool)
+            .await?;
+        let token = match token {
+            Some(token) => token,
+            None => return Ok(Remembered::Invalid),
+        };
+
+        let hashed = hash_token(validator);
+        if constant_time_eq(hashed.as_bytes(), token.validator_hash.as_bytes()) {
+            let new_validator = random_token(32);
+            let q = sql("UPDATE remember_tokens
+                SET validator_hash = ?, previous_hash = ?, rotated_at = ?
+                WHERE selector = ? AND validator_hash = ?");
+            let result = sqlx::query(&q)
+                .bind(hash_token(&new_validator))
+                .bind(&token.validator_hash)
+                .bind(Utc::now())
+                .bind(selector)
+                .bind(&token.validator_hash)
+                .execute(&self.pool)
+                .await?;
+
+            // another request rotated it first, and that one sets the new cookie
+            let cookie =
+                (result.rows_affected() > 0).then(|| format!("{selector}:{new_validator}"));
+            return Ok(Remembered::User {
+                user_id: token.user_id,
+                cookie,
+            });
+        }
+
+        let recently_rotated = token.rotated_at.is_some_and(|rotated_at| {
+            rotated_at + Duration::seconds(ROTATION_GRACE_SECONDS) > Utc::now()
+        });
+        let previous = token
+            .previous_hash
+            .is_some_and(|previous| constant_time_eq(hashed.as_bytes(), previous.as_bytes()));
+        if previous && recently_rotated {
+            return Ok(Remembered::User {
+                user_id: token.user_id,
+                cookie: None,
+            });
+        }
+
+        tracing::warn!(
+            user_id = token.user_id,
+            "remember-me token reused, deleting all of the user's tokens"
+        );
+        self.forget_user(&token.user_id).await?;
+        Ok(Remembered::Invalid)
+    }
+
+    /// deletes the token in the cookie, when logging out
+    pub(crate) async fn forget(&self, cookie: &str) -> Result<(), ErrResponse> {
+        let selector = cookie.split_once(':').map_or(cookie, |(s, _)| s);
+        sqlx::query(&sql("DELETE FROM remember_tokens WHERE selector = ?"))
+            .bind(selector)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// deletes all of the user's tokens, eg after changing the password
+    pub async fn forget_user(&self, user_id: &str) -> Result<u64, ErrResponse> {
+        let result = sqlx::query(&sql("DELETE FROM remember_tokens WHERE user_id = ?"))
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// deletes expired tokens, returns how many were deleted
+    pub async fn cleanup(&self) -> Result<u64, ErrResponse> {
+        let result = sqlx::query(&sql("DELETE FROM remember_tokens WHERE expires < ?"))
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+
+    fn user_id(remembered: &Remembered) -> Option<&str> {
+        match remembered {
+            Remembered::User { user_id, .. } => Some(user_id),
+            Remembered::Invalid => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rotation_and_theft() {
+        let remember = RememberMe::new(test_pool().await);
+        let first = remember.issue("1").await.unwrap();
+
+        let second = match remember.consume(&first).await.unwrap() {
+            Remembered::User {
+                user_id,
+                cookie: Some(cookie),
+            } => {
+                assert_eq!(user_id, "1");
+                cookie
+            }
+            _ => panic!("token should be valid"),
+        };
+
+        // a parallel request with the old cookie, right after rotating
+        assert_eq!(user_id(&remember.consume(&first).await.unwrap()), Some("1"));
+        assert!(user_id(&remember.consume("nope:nope").await.unwrap()).is_none());
+
+        // the old cookie shows up again later, so it was stolen
+        sqlx::query("UPDATE remember_tokens SET rotated_at = ?")
+            .bind(Utc::now() - Duration::minutes(5))
+            .execute(&remember.pool)
+            .await
+            .unwrap();
+        assert!(user_id(&remember.consume(&first).await.unwrap()).is_none());
+        assert!(user_id(&remember.consume(&second).await.unwrap()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_with_config_keeps_name_and_lifetime() {
+        let remember =
+            RememberMe::new(test_pool().await).with_config(SessionConfig::new().with_secure(false));
+        assert_eq!(remember.config().name, REMEMBER_COOKIE_NAME);
+        assert_eq!(remember.config().lifetime, Duration::days(30));
+        assert!(!remember.config().secure);
+
+        let remember = remember
+            .with_name("remember")
+            .with_lifetime(Duration::days(7));
+        assert_eq!(remember.config().name, "remember");
+        assert_eq!(remember.config().lifetime, Duration::days(7));
+    }
+}
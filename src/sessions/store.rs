@@ -0,0 +1,53 @@
+use super::{SessionChanges, SessionConfig};
+use crate::errors::ErrResponse;
+use async_session::Session;
+use axum::{async_trait, http::StatusCode};
+
+/// backend used by `session_middleware` to persist sessions
+///
+/// `DbSessionStore` is the usual one, `MemoryStore` and `FileStore` are handy
+/// for tests and small tools that don't have a database
+#[async_trait]
+pub trait SessionStore: Send + Sync + 'static {
+    /// loads the session corresponding to `cookie_value`
+    /// returns `None` if it doesn't exist or has expired
+    async fn load_session(&self, cookie_value: String) -> Result<Option<Session>, ErrResponse>;
+
+    /// stores the session, and returns its cookie value
+    /// cloned sessions don't contain the cookie value, so they return `None`
+    async fn store_session(&self, session: Session) -> Result<Option<String>, ErrResponse>;
+
+    /// stores only the keys that changed during the request, on top of the latest stored
+    /// version of the session, so parallel requests don't overwrite each other
+    ///
+    /// returns `None` if `changes.was_loaded()` but the session is gone, because another
+    /// request destroyed it
+    ///
+    /// used by `session_middleware`, the default ignores `changes` and stores the whole session
+    async fn store_changes(
+        &self,
+        session: Session,
+        changes: &SessionChanges,
+    ) -> Result<Option<String>, ErrResponse> {
+        let _ = changes;
+        self.store_session(session).await
+    }
+
+    /// removes the session from the store
+    async fn destroy_session(&self, session: Session) -> Result<(), ErrResponse>;
+
+    /// removes every expired session, and returns how many were removed
+    async fn cleanup(&self) -> Result<u64, ErrResponse>;
+
+    /// cookie attributes and expiry for sessions in this store
+    fn config(&self) -> &SessionConfig;
+}
+
+pub(crate) fn id_from_cookie_value(cookie_value: &str) -> Result<String, ErrResponse> {
+    Session::id_from_cookie_value(cookie_value).map_err(|_| {
+        ErrResponse::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "couldn't get id out of cookie value".to_string(),
+        )
+    })
+}
@@ -0,0 +1,233 @@
+//! links that can't be forged, like password resets and unsubscribe links
+//!
+//! ```ignore
+//! let signer = UrlSigner::new(secret, config.clone());
+//! let url = signer.sign_with_expiry(route_download(file_id), Duration::hours(1));
+//!
+//! async fn download(_: SignedRequest, ...) { ... }
+//! ```
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, OriginalUri},
+    http::{request::Parts, Request, StatusCode, Uri},
+    middleware::Next,
+    response::Response,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt::Display;
+
+use crate::{config::Config, errors::ErrResponse};
+
+// prefixed, so that they don't clash with the app's own params
+const SIGNATURE_PARAM: &str = "_signature";
+const EXPIRES_PARAM: &str = "_expires";
+
+/// signs urls with a key derived from an app secret, and checks their signature
+///
+/// the path and the whole query are signed, so none of the params can be changed.
+/// added as an extension, it's used by `SignedRequest` and `require_signature`
+#[derive(Clone)]
+pub struct UrlSigner {
+    mac: Hmac<Sha256>,
+    config: Config,
+}
+
+impl UrlSigner {
+    /// `secret` should be long and random, and different from the one used for `CookieStore`
+    #[must_use]
+    pub fn new(secret: impl AsRef<[u8]>, config: Config) -> Self {
+        Self {
+            mac: Hmac::new_from_slice(secret.as_ref()).expect("hmac accepts keys of any size"),
+            config,
+        }
+    }
+
+    fn signature(&self, url: &str) -> String {
+        let mut mac = self.mac.clone();
+        mac.update(url.as_bytes());
+        URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+    }
+
+    /// absolute url to `route`, usually a `NamedRoute`, that never expires
+    pub fn sign(&self, route: impl Display) -> String {
+        self.sign_relative(route.to_string())
+    }
+
+    /// absolute url to `route`, usually a `NamedRoute`, that expires after `expires_in`
+    pub fn sign_with_expiry(&self, route: impl Display, expires_in: Duration) -> String {
+        let route = route.to_string();
+        let separator = if route.contains('?') { '&' } else { '?' };
+        let expires = (Utc::now() + expires_in).timestamp();
+        self.sign_relative(format!("{route}{separator}{EXPIRES_PARAM}={expires}"))
+    }
+
+    fn sign_relative(&self, url: String) -> String {
+        let separator = if url.contains('?') { '&' } else { '?' };
+        let signature = self.signature(&url);
+        self.config
+            .absolute_url(&format!("{url}{separator}{SIGNATURE_PARAM}={signature}"))
+    }
+
+    /// checks the signature and expiry of a request uri
+    #[track_caller]
+    pub fn verify(&self, uri: &Uri) -> Result<(), ErrResponse> {
+        let forbidden = |message| ErrResponse::new(StatusCode::FORBIDDEN, message);
+
+        let url = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+        // the signature is always the last param, and everything before it is signed
+        let (signed, signature) = url
+            .rsplit_once(&format!("&{SIGNATURE_PARAM}="))
+            .or_else(|| url.rsplit_once(&format!("?{SIGNATURE_PARAM}=")))
+            .ok_or_else(|| forbidden("missing signature"))?;
+
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| forbidden("invalid signature"))?;
+        let mut mac = self.mac.clone();
+        mac.update(signed.as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| forbidden("invalid signature"))?;
+
+        let query = signed.split_once('?').map(|(_, q)| q).unwrap_or_default();
+        let mut expires = None;
+        for (k, v) in form_urlencoded::parse(query.as_bytes()) {
+            if k == SIGNATURE_PARAM || (k == EXPIRES_PARAM && expires.is_some()) {
+                // the handler might read a different one than we did
+                return Err(forbidden("invalid signature"));
+            }
+            if k == EXPIRES_PARAM {
+                expires = Some(v.parse::<i64>());
+            }
+        }
+        match expires {
+            Some(Ok(expires)) if expires < Utc::now().timestamp() => Err(forbidden("link expired")),
+            Some(Err(_)) => Err(forbidden("invalid signature")),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn signer(parts: &Parts) -> &UrlSigner {
+    parts
+        .extensions
+        .get::<UrlSigner>()
+        .expect("`UrlSigner` extension missing")
+}
+
+/// the uri that was signed, nested routers strip their prefix from `parts.uri`
+fn original_uri(parts: &Parts) -> &Uri {
+    parts
+        .extensions
+        .get::<OriginalUri>()
+        .map(|uri| &uri.0)
+        .unwrap_or(&parts.uri)
+}
+
+/// only lets requests with a valid signature through, rejects the rest with a 403
+pub struct SignedRequest;
+
+#[async_trait]
+impl<S> FromRequestParts<S> for SignedRequest
+where
+    S: Send + Sync,
+{
+    type Rejection = ErrResponse;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        signer(parts).verify(original_uri(parts))?;
+        Ok(Self)
+    }
+}
+
+/// same as `SignedRequest`, for whole routers
+///
+/// ```ignore
+/// .route_layer(axum::middleware::from_fn(require_signature))
+/// ```
+pub async fn require_signature<B>(req: Request<B>, next: Next<B>) -> Result<Response, ErrResponse> {
+    let (parts, body) = req.into_parts();
+    signer(&parts).verify(original_uri(&parts))?;
+    Ok(next.run(Request::from_parts(parts, body)).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::helpers::*;
+    use axum::{extract::Extension, routing::get, Router};
+
+    fn signer() -> UrlSigner {
+        UrlSigner::new("secret", test_config())
+    }
+
+    fn verify(signer: &UrlSigner, url: &str) -> Result<(), ErrResponse> {
+        let url = url.strip_prefix("https://example.com").unwrap();
+        signer.verify(&url.parse().unwrap())
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let signer = signer();
+
+        let url = signer.sign("/unsubscribe/3?list=news");
+        assert!(url.starts_with("https://example.com/unsubscribe/3?list=news&_signature="));
+        assert!(verify(&signer, &url).is_ok());
+
+        let tampered = url.replace("/3?", "/4?");
+        assert!(verify(&signer, &tampered).is_err());
+        assert!(verify(&signer, "https://example.com/unsubscribe/3?list=news").is_err());
+
+        let url = signer.sign_with_expiry("/download/1", Duration::hours(1));
+        assert!(verify(&signer, &url).is_ok());
+        let url = signer.sign_with_expiry("/download/1", Duration::hours(-1));
+        assert!(verify(&signer, &url).is_err());
+
+        let other = UrlSigner::new("other secret", signer.config.clone());
+        assert!(verify(&other, &signer.sign("/download/1")).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_params() {
+        let signer = signer();
+        let expired = (Utc::now() - Duration::hours(1)).timestamp();
+        let later = (Utc::now() + Duration::hours(1)).timestamp();
+
+        let url = signer.sign(format!("/download/1?_expires={expired}&_expires={later}"));
+        assert!(verify(&signer, &url).is_err());
+        let url = signer.sign("/download/1?_signature=nope");
+        assert!(verify(&signer, &url).is_err());
+        // the app's own params aren't reserved
+        let url = signer.sign("/download/1?expires=never&signature=mine");
+        assert!(verify(&signer, &url).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_nested_routers() {
+        let signer = signer();
+        let app = Router::new()
+            .nest_service(
+                "/files",
+                Router::new().route("/:id", get(|_: SignedRequest| async { "ok" })),
+            )
+            .nest_service(
+                "/downloads",
+                Router::new()
+                    .route("/:id", get(|| async { "ok" }))
+                    .route_layer(axum::middleware::from_fn(require_signature)),
+            )
+            .layer(Extension(signer.clone()));
+        let path = |url: String| url.strip_prefix("https://example.com").unwrap().to_string();
+
+        for route in ["/files/1", "/downloads/1"] {
+            let res = app.clone().req(empty_get(path(signer.sign(route)))).await;
+            assert!(res.is_ok());
+            let tampered = path(signer.sign(route)).replace("/1?", "/2?");
+            let res = app.clone().req(empty_get(tampered)).await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        }
+    }
+}
@@ -1,7 +1,10 @@
 use async_session::async_trait;
 use axum::{
     body::{Body, HttpBody},
-    http::{Request, StatusCode},
+    http::{
+        header::{COOKIE, SET_COOKIE},
+        HeaderValue, Request, StatusCode,
+    },
     Router,
 };
 use http::{response::Parts, uri::Uri};
@@ -36,6 +39,27 @@ where
         .unwrap()
 }
 
+/// config for an app at `https://example.com`
+#[allow(dead_code)]
+pub fn test_config() -> crate::config::Config {
+    crate::config::Config::new(
+        std::path::PathBuf::new(),
+        std::path::PathBuf::new(),
+        "https://example.com".to_string(),
+        "muxa".to_string(),
+        "/uploaded".to_string(),
+    )
+}
+
+/// a get request sending `cookie`, eg from `session_cookie`
+#[allow(dead_code)]
+pub fn with_cookie(uri: &str, cookie: &str) -> Request<Body> {
+    let mut req = empty_get(uri);
+    req.headers_mut()
+        .insert(COOKIE, HeaderValue::from_str(cookie).unwrap());
+    req
+}
+
 pub struct TestResponse {
     pub parts: Parts,
     pub bytes: bytes::Bytes,
@@ -61,6 +85,13 @@ impl TestResponse {
     }
 }
 
+/// the `name=value` part of the response's set-cookie header
+#[allow(dead_code)]
+pub fn session_cookie(res: &TestResponse) -> String {
+    let header = res.parts.headers[SET_COOKIE].to_str().unwrap();
+    header.split(';').next().unwrap().to_string()
+}
+
 #[async_trait]
 pub trait RouterExt {
     async fn req(self, req: Request<Body>) -> TestResponse;
@@ -69,7 +100,8 @@ pub trait RouterExt {
 impl RouterExt for Router {
     async fn req(self, req: Request<Body>) -> TestResponse {
         let (parts, mut body) = self.oneshot(req).await.unwrap().into_parts();
-        let output = body.data().await.unwrap().unwrap();
+        // redirects and other responses can have an empty body
+        let output = body.data().await.transpose().unwrap().unwrap_or_default();
         TestResponse {
             parts,
             bytes: output,
@@ -1,4 +1,6 @@
-use validator::ValidationError;
+use serde_json::Value;
+use std::collections::HashMap;
+use validator::{ValidationError, ValidationErrors, ValidationErrorsKind};
 
 pub fn alpha_dash(s: &str) -> Result<(), ValidationError> {
     if !s
@@ -18,3 +20,135 @@ pub fn public_private(p: &str) -> Result<(), ValidationError> {
     }
     Ok(())
 }
+
+/// every error in `errors`, including the ones from nested structs and lists,
+/// keyed by their path, eg `address.city` or `items[2].qty`
+pub fn flatten_errors(errors: &ValidationErrors) -> HashMap<String, Vec<String>> {
+    let mut out = HashMap::new();
+    flatten_into(errors, None, &mut out);
+    out
+}
+
+fn flatten_into(
+    errors: &ValidationErrors,
+    prefix: Option<&str>,
+    out: &mut HashMap<String, Vec<String>>,
+) {
+    for (field, kind) in errors.errors() {
+        let path = match prefix {
+            Some(prefix) => format!("{prefix}.{field}"),
+            None => field.to_string(),
+        };
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                out.entry(path)
+                    .or_default()
+                    .extend(errors.iter().map(error_message));
+            }
+            ValidationErrorsKind::Struct(errors) => flatten_into(errors, Some(&path), out),
+            ValidationErrorsKind::List(items) => {
+                for (i, errors) in items {
+                    flatten_into(errors, Some(&format!("{path}[{i}]")), out);
+                }
+            }
+        }
+    }
+}
+
+/// the error's message, with `{param}` replaced by the value of each param
+///
+/// errors without a message get their code and params, eg `length (min: 3, max: 20)`
+pub fn error_message(error: &ValidationError) -> String {
+    let message = match &error.message {
+        Some(message) => message,
+        None => return fallback_message(error),
+    };
+    let mut message = message.to_string();
+    for (name, value) in &error.params {
+        message = message.replace(&format!("{{{name}}}"), &param_value(value));
+    }
+    message
+}
+
+fn fallback_message(error: &ValidationError) -> String {
+    // `value` is what was validated, which could be a password
+    let mut params: Vec<_> = error
+        .params
+        .iter()
+        .filter(|(name, _)| *name != "value")
+        .map(|(name, value)| format!("{name}: {}", param_value(value)))
+        .collect();
+    if params.is_empty() {
+        return error.code.to_string();
+    }
+    // params are in a HashMap, sort them so messages don't change between runs
+    params.sort();
+    format!("{} ({})", error.code, params.join(", "))
+}
+
+fn param_value(value: &Value) -> String {
+    match value {
+        // if we do to_string directly, strings get "" around them
+        Value::String(s) => s.clone(),
+        // range params are always floats, `1.0` reads better as `1`
+        Value::Number(n) => match n.as_f64() {
+            Some(f) if f.fract() == 0.0 && f.abs() < 1e15 => (f as i64).to_string(),
+            _ => n.to_string(),
+        },
+        v => v.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use validator::Validate;
+
+    #[derive(Validate)]
+    struct Item {
+        #[validate(range(min = 1, message = "must be at least {min}"))]
+        qty: i32,
+    }
+
+    #[derive(Validate)]
+    struct Address {
+        #[validate(length(min = 1))]
+        city: String,
+    }
+
+    #[derive(Validate)]
+    struct Order {
+        #[validate(custom = "alpha_dash")]
+        name: String,
+        #[validate]
+        address: Address,
+        #[validate]
+        items: Vec<Item>,
+    }
+
+    #[test]
+    fn test_nested_errors() {
+        let order = Order {
+            name: "not valid".to_string(),
+            address: Address {
+                city: String::new(),
+            },
+            items: vec![Item { qty: 1 }, Item { qty: 0 }],
+        };
+        let errors = flatten_errors(&order.validate().unwrap_err());
+
+        assert_eq!(errors["name"], vec!["not alpha-dash"]);
+        assert_eq!(errors["address.city"], vec!["length (min: 1)"]);
+        assert_eq!(errors["items[1].qty"], vec!["must be at least 1"]);
+        assert_eq!(errors.len(), 3);
+    }
+
+    #[test]
+    fn test_fallback_message() {
+        let mut error = ValidationError::new("length");
+        error.add_param("max".into(), &20);
+        error.add_param("min".into(), &3);
+        error.add_param("value".into(), &"hunter2");
+        assert_eq!(error_message(&error), "length (max: 20, min: 3)");
+    }
+}